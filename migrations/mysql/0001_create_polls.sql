@@ -0,0 +1,26 @@
+CREATE TABLE polls (
+    id                             INT          NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    guild_id                       BIGINT       NOT NULL,
+    question                       TEXT         NOT NULL,
+    channel_id                     BIGINT       NOT NULL,
+    result_channel_id              BIGINT       NULL,
+    pin                            BOOLEAN      NOT NULL,
+    mention_role                   BOOLEAN      NOT NULL,
+    delete_after_results           BOOLEAN      NOT NULL,
+    custom                         BOOLEAN      NOT NULL,
+    role_id_needed                 BIGINT       NULL,
+    vote_percentage_needed_to_pass SMALLINT     NOT NULL,
+    max_votes_per_user             SMALLINT     NOT NULL,
+    created_at                     TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX polls_guild_id ON polls (guild_id);
+
+CREATE TABLE poll_options (
+    id       INT          NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    poll_id  INT          NOT NULL,
+    position INT          NOT NULL,
+    positive BOOLEAN      NOT NULL,
+    value    VARCHAR(255) NOT NULL,
+    FOREIGN KEY (poll_id) REFERENCES polls (id) ON DELETE CASCADE
+);
@@ -0,0 +1,16 @@
+CREATE TABLE poll_settings (
+    guild_id   BIGINT    NOT NULL PRIMARY KEY,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE poll_settings_channels (
+    guild_id   BIGINT NOT NULL,
+    channel_id BIGINT NOT NULL,
+    PRIMARY KEY (guild_id, channel_id)
+);
+
+CREATE TABLE poll_settings_changes (
+    guild_id    BIGINT      NOT NULL,
+    change_name VARCHAR(64) NOT NULL,
+    PRIMARY KEY (guild_id, change_name)
+);
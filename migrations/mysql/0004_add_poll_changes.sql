@@ -0,0 +1,6 @@
+ALTER TABLE polls ADD COLUMN change_name VARCHAR(64) NULL;
+ALTER TABLE polls ADD COLUMN change_key VARCHAR(255) NULL;
+ALTER TABLE polls ADD COLUMN change_value VARCHAR(255) NULL;
+ALTER TABLE polls ADD COLUMN change_status VARCHAR(16) NULL;
+ALTER TABLE polls ADD COLUMN change_error TEXT NULL;
+ALTER TABLE polls ADD COLUMN change_executed_at TIMESTAMP NULL;
@@ -0,0 +1,25 @@
+CREATE TABLE polls (
+    id                             INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    guild_id                       INTEGER NOT NULL,
+    question                       TEXT    NOT NULL,
+    channel_id                     INTEGER NOT NULL,
+    result_channel_id              INTEGER NULL,
+    pin                            BOOLEAN NOT NULL,
+    mention_role                   BOOLEAN NOT NULL,
+    delete_after_results           BOOLEAN NOT NULL,
+    custom                         BOOLEAN NOT NULL,
+    role_id_needed                 INTEGER NULL,
+    vote_percentage_needed_to_pass INTEGER NOT NULL,
+    max_votes_per_user             INTEGER NOT NULL,
+    created_at                     TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX polls_guild_id ON polls (guild_id);
+
+CREATE TABLE poll_options (
+    id       INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    poll_id  INTEGER NOT NULL REFERENCES polls (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    positive BOOLEAN NOT NULL,
+    value    TEXT    NOT NULL
+);
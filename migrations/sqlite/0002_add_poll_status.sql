@@ -0,0 +1,3 @@
+ALTER TABLE polls ADD COLUMN status TEXT NOT NULL DEFAULT 'draft';
+
+CREATE INDEX polls_guild_id_status ON polls (guild_id, status);
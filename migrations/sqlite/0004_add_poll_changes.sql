@@ -0,0 +1,6 @@
+ALTER TABLE polls ADD COLUMN change_name TEXT NULL;
+ALTER TABLE polls ADD COLUMN change_key TEXT NULL;
+ALTER TABLE polls ADD COLUMN change_value TEXT NULL;
+ALTER TABLE polls ADD COLUMN change_status TEXT NULL;
+ALTER TABLE polls ADD COLUMN change_error TEXT NULL;
+ALTER TABLE polls ADD COLUMN change_executed_at TIMESTAMP NULL;
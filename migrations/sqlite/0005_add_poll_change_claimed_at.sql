@@ -0,0 +1 @@
+ALTER TABLE polls ADD COLUMN change_claimed_at INTEGER NULL;
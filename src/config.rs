@@ -0,0 +1,405 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use strum_macros::EnumString;
+
+use crate::helpers::database::base::Backend;
+use crate::helpers::database::base::DatabaseSettings;
+use crate::oauth::models::OauthScope;
+
+/// Read when `CONFIG_FILE` isn't set, the server runs from the environment alone without it.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Everything the server reads at startup. Values come from the optional TOML file first and
+/// are then overridden by the environment, so a `.env` file keeps working on its own.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub discord: DiscordConfig,
+    pub session: SessionConfig,
+    pub redis: RedisConfig,
+    pub database: DatabaseSettings,
+    pub cache: CacheConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    /// Where the dashboard is served, Discord redirects logins back to it.
+    pub client_uri: String,
+    /// Origins allowed to call the API with credentials, just the dashboard when empty.
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "127.0.0.1:8080".into(),
+            client_uri: String::new(),
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DiscordConfig {
+    pub base_url: String,
+    pub client_id: u64,
+    pub client_secret: String,
+    pub bot_token: String,
+    /// Upper bound on the members fetched per guild.
+    pub max_members: usize,
+    /// Asked for at login on top of the scopes the dashboard needs.
+    pub oauth_scopes: Vec<OauthScope>,
+    /// Turns the deprecated GET login routes back on. They take the same state and answer with
+    /// the same session cookie as the POST routes, so clients need those changes either way.
+    pub legacy_get_routes: bool,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://discord.com/api".into(),
+            client_id: 0,
+            client_secret: String::new(),
+            bot_token: String::new(),
+            max_members: 100_000,
+            oauth_scopes: Vec::new(),
+            legacy_get_routes: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Encrypts the stored Discord tokens and keys the access token hashes.
+    pub secret: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RedisConfig {
+    pub url: String,
+    /// Connections shared by all workers.
+    pub pool_size: u32,
+    /// How long to wait for a connection or a reply before treating Redis as unavailable.
+    pub timeout_ms: u64,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1/".into(),
+            pool_size: 16,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Deserialize, EnumString, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CacheBackend {
+    #[default]
+    Redis,
+    /// Kept in the process, for single instance setups and tests without Redis.
+    Memory,
+}
+
+/// Where cached values live and for how long, TTLs are in seconds.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub backend: CacheBackend,
+    /// Entries the memory backend keeps before evicting the least recently used.
+    pub memory_capacity: usize,
+    pub guilds_ttl: usize,
+    pub channels_ttl: usize,
+    pub roles_ttl: usize,
+    pub members_ttl: usize,
+    /// Cached guilds, channels, roles and members older than this are still served but
+    /// refreshed from Discord in the background.
+    pub stale_after: usize,
+    /// How long a login attempt may take.
+    pub oauth_state_ttl: usize,
+    /// Sessions expire this long after they were created or their Discord token was last
+    /// refreshed.
+    pub session_ttl: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::Redis,
+            memory_capacity: 10_000,
+            guilds_ttl: 1800,
+            channels_ttl: 1800,
+            roles_ttl: 1800,
+            members_ttl: 1800,
+            stale_after: 300,
+            oauth_state_ttl: 600,
+            session_ttl: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Every problem found while loading, reported together so they can be fixed in one go.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for error in self.0.iter() {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_string(target: &mut String, name: &str) {
+    if let Ok(value) = env::var(name) {
+        *target = value;
+    }
+}
+
+fn set_parsed<T: FromStr>(target: &mut T, name: &str, errors: &mut Vec<String>)
+where
+    T::Err: fmt::Display,
+{
+    if let Ok(value) = env::var(name) {
+        match value.trim().parse::<T>() {
+            Ok(value) => *target = value,
+            Err(err) => errors.push(format!("{} `{}` is invalid: {}", name, value, err)),
+        }
+    }
+}
+
+fn set_list<T: FromStr>(target: &mut Vec<T>, name: &str, errors: &mut Vec<String>)
+where
+    T::Err: fmt::Display,
+{
+    if let Ok(value) = env::var(name) {
+        let mut values = Vec::new();
+        for item in value.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match item.parse::<T>() {
+                Ok(item) => values.push(item),
+                Err(err) => errors.push(format!("{} contains invalid `{}`: {}", name, item, err)),
+            }
+        }
+        *target = values;
+    }
+}
+
+fn require(value: &str, name: &str, errors: &mut Vec<String>) {
+    if value.trim().is_empty() {
+        errors.push(format!("{} is required.", name));
+    }
+}
+
+fn check_url(value: &str, name: &str, schemes: &[&str], errors: &mut Vec<String>) {
+    match url::Url::parse(value) {
+        Ok(url) if schemes.contains(&url.scheme()) => {}
+        Ok(url) => errors.push(format!(
+            "{} `{}` must use {}, not {}.",
+            name,
+            value,
+            schemes.join(" or "),
+            url.scheme()
+        )),
+        Err(err) => errors.push(format!("{} `{}` is not a valid url: {}", name, value, err)),
+    }
+}
+
+impl Config {
+    /// Reads the TOML file named by `CONFIG_FILE` (or `config.toml` if present), applies the
+    /// environment and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match env::var("CONFIG_FILE") {
+            Ok(path) => Self::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            Err(_) => Self::default(),
+        };
+
+        let mut errors = Vec::new();
+        config.apply_env(&mut errors);
+        config.validate(&mut errors);
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(errors))
+        }
+    }
+
+    fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| ConfigError(vec![format!("Couldn't read {}: {}", path, e)]))?;
+        toml::from_str(&content).map_err(|e| ConfigError(vec![format!("{}: {}", path, e)]))
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        set_string(&mut self.server.bind_address, "BIND_ADDRESS");
+        set_string(&mut self.server.client_uri, "CLIENT_URI");
+        set_list(&mut self.server.cors_origins, "CORS_ORIGINS", errors);
+
+        set_string(&mut self.discord.base_url, "DISCORD_BASE_URL");
+        set_parsed(&mut self.discord.client_id, "DISCORD_CLIENT_ID", errors);
+        set_string(&mut self.discord.client_secret, "DISCORD_CLIENT_SECRET");
+        set_string(&mut self.discord.bot_token, "DISCORD_CLIENT_TOKEN");
+        set_parsed(&mut self.discord.max_members, "DISCORD_MAX_MEMBERS", errors);
+        set_list(&mut self.discord.oauth_scopes, "OAUTH_SCOPES", errors);
+        set_parsed(
+            &mut self.discord.legacy_get_routes,
+            "OAUTH_LEGACY_GET_ROUTES",
+            errors,
+        );
+
+        set_string(&mut self.session.secret, "SESSION_SECRET");
+        set_string(&mut self.redis.url, "REDIS_URL");
+        set_parsed(&mut self.redis.pool_size, "REDIS_POOL_SIZE", errors);
+        set_parsed(&mut self.redis.timeout_ms, "REDIS_TIMEOUT_MS", errors);
+
+        let database = &mut self.database;
+        set_parsed(&mut database.backend, "DB_BACKEND", errors);
+        set_string(&mut database.host, "DB_HOST");
+        set_string(&mut database.name, "DB_NAME");
+        set_string(&mut database.user, "DB_USER");
+        set_string(&mut database.password, "DB_PASSWORD");
+        if let Ok(url) = env::var("DATABASE_URL") {
+            database.url = Some(url);
+        }
+
+        let cache = &mut self.cache;
+        set_parsed(&mut cache.backend, "CACHE_BACKEND", errors);
+        set_parsed(&mut cache.memory_capacity, "CACHE_MEMORY_CAPACITY", errors);
+        set_parsed(&mut cache.guilds_ttl, "CACHE_GUILDS_TTL", errors);
+        set_parsed(&mut cache.channels_ttl, "CACHE_CHANNELS_TTL", errors);
+        set_parsed(&mut cache.roles_ttl, "CACHE_ROLES_TTL", errors);
+        set_parsed(&mut cache.members_ttl, "CACHE_MEMBERS_TTL", errors);
+        set_parsed(&mut cache.stale_after, "CACHE_STALE_AFTER", errors);
+        set_parsed(&mut cache.oauth_state_ttl, "CACHE_OAUTH_STATE_TTL", errors);
+        set_parsed(&mut cache.session_ttl, "CACHE_SESSION_TTL", errors);
+    }
+
+    fn validate(&mut self, errors: &mut Vec<String>) {
+        // Paths are appended to these, a trailing slash would double up.
+        for url in [&mut self.server.client_uri, &mut self.discord.base_url] {
+            url.truncate(url.trim_end_matches('/').len());
+        }
+
+        let server = &mut self.server;
+        match server.bind_address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => errors.push(format!(
+                "BIND_ADDRESS `{}` must look like host:port.",
+                server.bind_address
+            )),
+        }
+        require(&server.client_uri, "CLIENT_URI", errors);
+        if !server.client_uri.is_empty() {
+            check_url(&server.client_uri, "CLIENT_URI", &["http", "https"], errors);
+        }
+        if server.cors_origins.is_empty() {
+            server.cors_origins.push(server.client_uri.clone());
+        } else {
+            for origin in server.cors_origins.iter() {
+                check_url(origin, "CORS_ORIGINS", &["http", "https"], errors);
+            }
+        }
+
+        let discord = &self.discord;
+        check_url(
+            &discord.base_url,
+            "DISCORD_BASE_URL",
+            &["http", "https"],
+            errors,
+        );
+        if discord.client_id == 0 {
+            errors.push("DISCORD_CLIENT_ID is required.".into());
+        }
+        require(&discord.client_secret, "DISCORD_CLIENT_SECRET", errors);
+        require(&discord.bot_token, "DISCORD_CLIENT_TOKEN", errors);
+        if discord.max_members == 0 {
+            errors.push("DISCORD_MAX_MEMBERS must be at least 1.".into());
+        }
+
+        require(&self.session.secret, "SESSION_SECRET", errors);
+        check_url(
+            &self.redis.url,
+            "REDIS_URL",
+            &["redis", "rediss", "unix"],
+            errors,
+        );
+        if self.redis.pool_size == 0 {
+            errors.push("REDIS_POOL_SIZE must be at least 1.".into());
+        }
+        if self.redis.timeout_ms == 0 {
+            errors.push("REDIS_TIMEOUT_MS must be at least 1.".into());
+        }
+
+        let database = &mut self.database;
+        if let Some(url) = database.url.clone() {
+            if let Err(err) = database.apply_url(&url) {
+                errors.push(format!("DATABASE_URL is invalid: {}", err));
+            }
+        }
+        require(&database.name, "DB_NAME", errors);
+        if database.backend == Backend::MySql {
+            require(&database.host, "DB_HOST", errors);
+            require(&database.user, "DB_USER", errors);
+            require(&database.password, "DB_PASSWORD", errors);
+        }
+
+        let cache = &self.cache;
+        if cache.memory_capacity == 0 {
+            errors.push("CACHE_MEMORY_CAPACITY must be at least 1.".into());
+        }
+        for (ttl, name) in [
+            (cache.guilds_ttl, "CACHE_GUILDS_TTL"),
+            (cache.channels_ttl, "CACHE_CHANNELS_TTL"),
+            (cache.roles_ttl, "CACHE_ROLES_TTL"),
+            (cache.members_ttl, "CACHE_MEMBERS_TTL"),
+            (cache.stale_after, "CACHE_STALE_AFTER"),
+            (cache.oauth_state_ttl, "CACHE_OAUTH_STATE_TTL"),
+            (cache.session_ttl, "CACHE_SESSION_TTL"),
+        ] {
+            if ttl == 0 {
+                errors.push(format!("{} must be at least 1 second.", name));
+            }
+        }
+    }
+
+    /// Makes the config available to code that isn't handed the `web::Data`, such as the
+    /// caches and repositories. Only the first call has an effect.
+    pub fn install(self) -> &'static Config {
+        CONFIG.get_or_init(|| self)
+    }
+}
+
+/// The config loaded at startup.
+/// Installs the defaults with a fixed secret, for tests that reach code using `get_config`.
+#[cfg(test)]
+pub fn install_test_config() -> &'static Config {
+    let mut config = Config::default();
+    config.session.secret = "test secret".into();
+    config.install()
+}
+
+pub fn get_config() -> &'static Config {
+    CONFIG
+        .get()
+        .expect("The config is installed before the server starts")
+}
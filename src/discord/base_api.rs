@@ -1,49 +1,172 @@
+use actix_web::rt::time::delay_for;
 use async_trait::async_trait;
+use once_cell::sync::Lazy;
 use reqwest;
 use reqwest::header::HeaderMap;
+use reqwest::header::HeaderValue;
+use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 
+use crate::helpers::errors::ApiError;
+
+use super::rate_limit::RateLimiter;
+use super::rate_limit::RetryAfter;
+use super::rate_limit::Route;
+
+/// Shared between all calls so connections are pooled and kept alive.
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+/// How often a call is retried after being rate limited before giving up.
+const MAX_RATE_LIMIT_RETRIES: usize = 3;
+
+pub fn get_http_client() -> &'static reqwest::Client {
+    &HTTP_CLIENT
+}
+
+/// Whose token a call is authorized with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    User,
+    Bot,
+}
+
+#[derive(Debug)]
 pub enum HttpMethod {
     Get,
+    Post,
+    Put,
+    /// Used by Discord's modify endpoints for channels, roles and members, which no change
+    /// calls yet.
+    #[allow(dead_code)]
+    Patch,
+    Delete,
 }
 
 pub trait Endpoint<D: DeserializeOwned> {
     const METHOD: HttpMethod = HttpMethod::Get;
 
     fn get_endpoint(&self) -> String;
+
+    /// The JSON body to send, see `to_body` for building it from a serializable struct.
+    fn get_body(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    fn get_query(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// Headers sent on top of the default headers of the `Callable`.
+    fn get_headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
+}
+
+pub fn to_body<T: Serialize>(body: &T) -> Option<serde_json::Value> {
+    serde_json::to_value(body).ok()
+}
+
+/// Headers that make the reason show up next to the action in the guild's audit log.
+pub fn audit_log_reason(reason: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(reason) = reason {
+        let encoded: String = url::form_urlencoded::byte_serialize(reason.as_bytes())
+            .collect::<String>()
+            .replace('+', "%20");
+        if let Ok(value) = HeaderValue::from_str(&encoded) {
+            headers.insert("X-Audit-Log-Reason", value);
+        }
+    }
+    headers
 }
 
 #[async_trait]
 pub trait Callable {
-    const BASE_URI: &'static str;
-
-    async fn call<T: Endpoint<D> + Send, D: DeserializeOwned>(
-        &self,
-        endpoint: T,
-    ) -> Result<D, String> {
-        let uri = format!("{}{}", Self::BASE_URI, endpoint.get_endpoint());
-        let client = reqwest::Client::new();
-        let response = match T::METHOD {
-            HttpMethod::Get => client.get(&uri),
-        }
-        .headers(self.get_default_headers().unwrap_or(HeaderMap::new()))
-        .send()
-        .await
-        .map_err(|e| format!("{}", e))?;
-        response
-            .error_for_status_ref()
-            .map_err(|e| format!("{}", e))?;
-
-        let json = response
-            .json::<D>()
-            .await
-            .map_err(|e| format!("Error json decoding {}: `{}`", uri, e))?;
-        Ok(json)
+    fn get_base_uri(&self) -> &str;
+
+    async fn call<T, D>(&self, endpoint: T) -> Result<D, ApiError>
+    where
+        T: Endpoint<D> + Send,
+        D: DeserializeOwned,
+    {
+        let path = endpoint.get_endpoint();
+        let uri = format!("{}{}", self.get_base_uri(), path);
+        let route = Route::new(&T::METHOD, &path);
+        let client = get_http_client();
+        let mut attempt = 0;
+
+        let response = loop {
+            if let Some(limiter) = self.get_rate_limiter() {
+                limiter.acquire(&route).await;
+            }
+
+            let mut request = match T::METHOD {
+                HttpMethod::Get => client.get(&uri),
+                HttpMethod::Post => client.post(&uri),
+                HttpMethod::Put => client.put(&uri),
+                HttpMethod::Patch => client.patch(&uri),
+                HttpMethod::Delete => client.delete(&uri),
+            }
+            .headers(self.get_default_headers().unwrap_or_default())
+            .headers(endpoint.get_headers());
+            let query = endpoint.get_query();
+            if !query.is_empty() {
+                request = request.query(&query);
+            }
+            if let Some(body) = endpoint.get_body() {
+                request = request.json(&body);
+            }
+            let response = request.send().await.map_err(ApiError::from_reqwest)?;
+
+            if let Some(limiter) = self.get_rate_limiter() {
+                limiter.update(&route, response.headers());
+            }
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = RetryAfter::from_response(response).await;
+                if let (true, Some(limiter)) = (retry_after.global, self.get_rate_limiter()) {
+                    limiter.set_global(retry_after.duration);
+                }
+                if attempt >= MAX_RATE_LIMIT_RETRIES {
+                    return Err(ApiError::RateLimited(retry_after.duration));
+                }
+                attempt += 1;
+                delay_for(retry_after.duration).await;
+                continue;
+            }
+            break response;
+        };
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            let body = response.json::<serde_json::Value>().await.ok();
+            return Err(ApiError::from_discord(status, body, self.get_token_kind()));
+        }
+
+        let decode_error = |e: serde_json::Error| ApiError::BadGateway {
+            message: format!("Error json decoding {}: `{}`", uri, e),
+            details: None,
+        };
+        // Endpoints without a response body declare `()`, which deserializes from null.
+        if status == StatusCode::NO_CONTENT {
+            return serde_json::from_value(serde_json::Value::Null).map_err(decode_error);
+        }
+
+        let body = response.bytes().await.map_err(ApiError::from_reqwest)?;
+        serde_json::from_slice::<D>(&body).map_err(decode_error)
     }
 
     fn get_default_headers(&self) -> Option<HeaderMap> {
         None
     }
+
+    fn get_token_kind(&self) -> TokenKind {
+        TokenKind::User
+    }
+
+    /// Tracks the buckets of the calls, without one a call only backs off once it got a 429.
+    fn get_rate_limiter(&self) -> Option<&RateLimiter> {
+        None
+    }
 }
 
 // println!("{}", uri);
@@ -51,3 +174,221 @@ pub trait Callable {
 //     println!("abc {:?}", response.text().await);
 //     return Err("".into());
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use mockito::Server;
+
+    use super::*;
+
+    struct TestCall {
+        base_uri: String,
+        rate_limiter: RateLimiter,
+        token_kind: TokenKind,
+    }
+
+    impl TestCall {
+        fn new(server: &Server) -> Self {
+            Self {
+                base_uri: server.url(),
+                rate_limiter: RateLimiter::default(),
+                token_kind: TokenKind::User,
+            }
+        }
+    }
+
+    impl Callable for TestCall {
+        fn get_base_uri(&self) -> &str {
+            &self.base_uri
+        }
+
+        fn get_token_kind(&self) -> TokenKind {
+            self.token_kind
+        }
+
+        fn get_rate_limiter(&self) -> Option<&RateLimiter> {
+            Some(&self.rate_limiter)
+        }
+    }
+
+    struct GetPath(&'static str);
+
+    impl Endpoint<serde_json::Value> for GetPath {
+        fn get_endpoint(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    fn block_on<F: Future + 'static>(future: F) -> F::Output {
+        actix_web::rt::System::new("test").block_on(future)
+    }
+
+    #[test]
+    fn retries_after_a_429() {
+        let mut server = Server::new();
+        let limited = server
+            .mock("GET", "/guilds/1/roles")
+            .with_status(429)
+            .with_body(r#"{"retry_after": 0.05, "global": false}"#)
+            .expect(1)
+            .create();
+        let ok = server
+            .mock("GET", "/guilds/1/roles")
+            .with_body("[]")
+            .expect(1)
+            .create();
+
+        let call = TestCall::new(&server);
+        let result = block_on(async move { call.call(GetPath("/guilds/1/roles")).await });
+
+        assert_eq!(result.unwrap(), serde_json::json!([]));
+        limited.assert();
+        ok.assert();
+    }
+
+    #[test]
+    fn waits_for_an_exhausted_bucket() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/guilds/1/channels")
+            .with_header("x-ratelimit-bucket", "channels")
+            .with_header("x-ratelimit-remaining", "0")
+            .with_header("x-ratelimit-reset-after", "0.3")
+            .with_body("[]")
+            .expect(2)
+            .create();
+
+        let call = TestCall::new(&server);
+        let second_call = block_on(async move {
+            call.call(GetPath("/guilds/1/channels")).await.unwrap();
+            let start = Instant::now();
+            call.call(GetPath("/guilds/1/channels")).await.unwrap();
+            start.elapsed()
+        });
+
+        assert!(second_call >= Duration::from_millis(250));
+        mock.assert();
+    }
+
+    #[test]
+    fn global_limit_blocks_every_route() {
+        let mut server = Server::new();
+        let limited = server
+            .mock("GET", "/guilds/1/roles")
+            .with_status(429)
+            .with_header("x-ratelimit-global", "true")
+            .with_header("retry-after", "0.4")
+            .expect(1)
+            .create();
+        let roles = server
+            .mock("GET", "/guilds/1/roles")
+            .with_body("[]")
+            .expect(1)
+            .create();
+        let channels = server
+            .mock("GET", "/channels/2")
+            .with_body("{}")
+            .expect(1)
+            .create();
+
+        let call = Rc::new(TestCall::new(&server));
+        let other_route = block_on(async move {
+            let start = Instant::now();
+            let limited_call = call.clone();
+            actix_web::rt::spawn(async move {
+                limited_call.call(GetPath("/guilds/1/roles")).await.unwrap();
+            });
+            actix_web::rt::time::delay_for(Duration::from_millis(100)).await;
+
+            call.call(GetPath("/channels/2")).await.unwrap();
+            let elapsed = start.elapsed();
+            actix_web::rt::time::delay_for(Duration::from_millis(100)).await;
+            elapsed
+        });
+
+        assert!(other_route >= Duration::from_millis(350));
+        limited.assert();
+        roles.assert();
+        channels.assert();
+    }
+
+    #[test]
+    fn gives_up_after_the_retry_limit() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/guilds/1/members")
+            .with_status(429)
+            .with_body(r#"{"retry_after": 0.01, "global": false}"#)
+            .expect(MAX_RATE_LIMIT_RETRIES + 1)
+            .create();
+
+        let call = TestCall::new(&server);
+        let result = block_on(async move { call.call(GetPath("/guilds/1/members")).await });
+
+        assert!(matches!(result, Err(ApiError::RateLimited(_))));
+        mock.assert();
+    }
+
+    #[test]
+    fn rejected_bot_token_is_not_a_user_error() {
+        let mut server = Server::new();
+        server.mock("GET", "/users/@me").with_status(401).create();
+        server.mock("GET", "/guilds/1").with_status(403).create();
+
+        let user = block_on({
+            let call = TestCall::new(&server);
+            async move { call.call(GetPath("/users/@me")).await }
+        });
+        let mut bot = TestCall::new(&server);
+        bot.token_kind = TokenKind::Bot;
+        let (unauthorized, forbidden) = block_on(async move {
+            (
+                bot.call(GetPath("/users/@me")).await,
+                bot.call(GetPath("/guilds/1")).await,
+            )
+        });
+
+        assert!(matches!(user, Err(ApiError::Unauthorized(_))));
+        assert!(matches!(unauthorized, Err(ApiError::Unavailable(_))));
+        assert!(matches!(forbidden, Err(ApiError::BadGateway { .. })));
+    }
+
+    struct RenameRole;
+
+    impl Endpoint<()> for RenameRole {
+        const METHOD: HttpMethod = HttpMethod::Patch;
+
+        fn get_endpoint(&self) -> String {
+            "/guilds/1/roles/2".into()
+        }
+
+        fn get_body(&self) -> Option<serde_json::Value> {
+            Some(serde_json::json!({ "name": "voters" }))
+        }
+    }
+
+    #[test]
+    fn sends_patch_with_a_body() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("PATCH", "/guilds/1/roles/2")
+            .match_header("content-type", "application/json")
+            .match_body(mockito::Matcher::Json(
+                serde_json::json!({ "name": "voters" }),
+            ))
+            .with_status(204)
+            .expect(1)
+            .create();
+
+        let call = TestCall::new(&server);
+        let result = block_on(async move { call.call(RenameRole).await });
+
+        assert!(result.is_ok());
+        mock.assert();
+    }
+}
@@ -1,4 +1,10 @@
+use super::base_api::audit_log_reason;
+use super::base_api::to_body;
 use super::base_api::Endpoint;
+use super::base_api::HttpMethod;
+use super::permissions::PermissionOverwrite;
+use super::permissions::RawPermissions;
+use reqwest::header::HeaderMap;
 use serde_repr::*;
 
 #[derive(serde::Deserialize, Debug)]
@@ -17,6 +23,15 @@ impl Endpoint<MeResponse> for GetMe {
 pub struct GuildResponse {
     pub id: String,
     pub name: String,
+    /// Hash of the guild's icon, none when it has no icon.
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// Whether the current user owns the guild.
+    #[serde(default)]
+    pub owner: bool,
+    /// The current user's permissions in the guild, including those of the owner.
+    #[serde(default)]
+    pub permissions: RawPermissions,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -25,11 +40,44 @@ pub struct GuildsResponse {
     pub guilds: Vec<GuildResponse>,
 }
 
-pub struct GetGuilds;
+pub struct GetGuilds {
+    /// Between 1 and 200, Discord defaults to 200.
+    pub limit: u16,
+    /// Only guilds with a higher id are returned.
+    pub after: Option<u64>,
+}
+
+impl GetGuilds {
+    pub const MAX_LIMIT: u16 = 200;
+}
+
 impl Endpoint<GuildsResponse> for GetGuilds {
     fn get_endpoint(&self) -> String {
         "/users/@me/guilds".into()
     }
+
+    fn get_query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![("limit", self.limit.to_string())];
+        if let Some(after) = self.after {
+            query.push(("after", after.to_string()));
+        }
+        query
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GuildDetailsResponse {
+    pub owner_id: String,
+}
+
+pub struct GetGuild {
+    pub guild_id: u64,
+}
+
+impl Endpoint<GuildDetailsResponse> for GetGuild {
+    fn get_endpoint(&self) -> String {
+        format!("/guilds/{}", self.guild_id)
+    }
 }
 
 #[derive(Serialize_repr, Deserialize_repr, PartialEq, Debug, Clone)]
@@ -54,6 +102,8 @@ pub struct ChannelResponse {
     pub name: String,
     #[serde(rename = "type")]
     pub kind: ChannelKind,
+    #[serde(default)]
+    pub permission_overwrites: Vec<PermissionOverwrite>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -76,6 +126,8 @@ impl Endpoint<ChannelsResponse> for GetChannels {
 pub struct RoleResponse {
     pub id: String,
     pub name: String,
+    pub permissions: RawPermissions,
+    pub position: i64,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -104,6 +156,9 @@ pub struct UserResponse {
 #[derive(serde::Deserialize, Debug)]
 pub struct MemberResponse {
     pub user: UserResponse,
+    pub nick: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -112,10 +167,107 @@ pub struct MembersResponse {
     pub members: Vec<MemberResponse>,
 }
 
-pub struct GetMembers(pub u64);
+pub struct GetMember {
+    pub guild_id: u64,
+    pub user_id: u64,
+}
+
+impl Endpoint<MemberResponse> for GetMember {
+    fn get_endpoint(&self) -> String {
+        format!("/guilds/{}/members/{}", self.guild_id, self.user_id)
+    }
+}
+
+pub struct GetMembers {
+    pub guild_id: u64,
+    /// Between 1 and 1000, Discord returns a single member when omitted.
+    pub limit: u16,
+    /// Only members with a higher user id are returned.
+    pub after: Option<u64>,
+}
+
+impl GetMembers {
+    pub const MAX_LIMIT: u16 = 1000;
+}
 
 impl Endpoint<MembersResponse> for GetMembers {
     fn get_endpoint(&self) -> String {
-        format!("/guilds/{}/members", self.0)
+        format!("/guilds/{}/members", self.guild_id)
+    }
+
+    fn get_query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![("limit", self.limit.to_string())];
+        if let Some(after) = self.after {
+            query.push(("after", after.to_string()));
+        }
+        query
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct CreateChannelBody {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ChannelKind,
+}
+
+pub struct CreateChannel {
+    pub guild_id: u64,
+    pub body: CreateChannelBody,
+    pub reason: Option<String>,
+}
+
+impl Endpoint<ChannelResponse> for CreateChannel {
+    const METHOD: HttpMethod = HttpMethod::Post;
+
+    fn get_endpoint(&self) -> String {
+        format!("/guilds/{}/channels", self.guild_id)
+    }
+
+    fn get_body(&self) -> Option<serde_json::Value> {
+        to_body(&self.body)
+    }
+
+    fn get_headers(&self) -> HeaderMap {
+        audit_log_reason(self.reason.as_deref())
+    }
+}
+
+pub struct DeleteChannel {
+    pub channel_id: u64,
+    pub reason: Option<String>,
+}
+
+impl Endpoint<ChannelResponse> for DeleteChannel {
+    const METHOD: HttpMethod = HttpMethod::Delete;
+
+    fn get_endpoint(&self) -> String {
+        format!("/channels/{}", self.channel_id)
+    }
+
+    fn get_headers(&self) -> HeaderMap {
+        audit_log_reason(self.reason.as_deref())
+    }
+}
+
+pub struct AddMemberRole {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub role_id: u64,
+    pub reason: Option<String>,
+}
+
+impl Endpoint<()> for AddMemberRole {
+    const METHOD: HttpMethod = HttpMethod::Put;
+
+    fn get_endpoint(&self) -> String {
+        format!(
+            "/guilds/{}/members/{}/roles/{}",
+            self.guild_id, self.user_id, self.role_id
+        )
+    }
+
+    fn get_headers(&self) -> HeaderMap {
+        audit_log_reason(self.reason.as_deref())
     }
 }
@@ -1,9 +1,23 @@
 use super::base_api::Callable;
+use super::base_api::TokenKind;
+use super::calls::GetMe;
+use super::models::parse_user_id;
+use super::rate_limit::RateLimiter;
 use actix_web::http::HeaderValue;
+use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
 use reqwest;
 use reqwest::header::HeaderMap;
 use reqwest::header::AUTHORIZATION;
 
+use crate::config::get_config;
+use crate::helpers::errors::ApiResult;
+
+/// Discord tracks limits per token, only the bot token is shared by all requests.
+static BOT_RATE_LIMITER: Lazy<RateLimiter> = Lazy::new(RateLimiter::default);
+
+static BOT_USER_ID: OnceCell<u64> = OnceCell::new();
+
 #[derive(Clone)]
 pub enum AccessToken {
     Bot(String),
@@ -18,10 +32,29 @@ impl DiscordCall {
     pub fn new(access_token: AccessToken) -> Self {
         Self { access_token }
     }
+
+    /// A call authorized with the bot's token.
+    pub fn bot() -> Self {
+        Self::new(AccessToken::Bot(get_config().discord.bot_token.clone()))
+    }
+}
+
+/// The user id of the bot, fetched once since it never changes.
+pub async fn get_bot_user_id() -> ApiResult<u64> {
+    if let Some(user_id) = BOT_USER_ID.get() {
+        return Ok(*user_id);
+    }
+
+    let call = DiscordCall::bot();
+    let me = call.call(GetMe).await?;
+    let user_id = parse_user_id(&me.id)?;
+    Ok(*BOT_USER_ID.get_or_init(|| user_id))
 }
 
 impl Callable for DiscordCall {
-    const BASE_URI: &'static str = "https://discord.com/api";
+    fn get_base_uri(&self) -> &str {
+        &get_config().discord.base_url
+    }
 
     fn get_default_headers(&self) -> Option<HeaderMap> {
         let mut params = HeaderMap::new();
@@ -33,4 +66,18 @@ impl Callable for DiscordCall {
         }
         Some(params)
     }
+
+    fn get_token_kind(&self) -> TokenKind {
+        match self.access_token {
+            AccessToken::Bot(_) => TokenKind::Bot,
+            AccessToken::Bearer(_) => TokenKind::User,
+        }
+    }
+
+    fn get_rate_limiter(&self) -> Option<&RateLimiter> {
+        match self.access_token {
+            AccessToken::Bot(_) => Some(&BOT_RATE_LIMITER),
+            AccessToken::Bearer(_) => None,
+        }
+    }
 }
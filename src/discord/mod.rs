@@ -2,5 +2,7 @@ pub mod base_api;
 pub mod calls;
 pub mod discord_base;
 pub mod models;
+pub mod permissions;
+pub mod rate_limit;
 pub mod routes;
 pub mod validation;
@@ -1,4 +1,17 @@
 use super::calls::ChannelKind;
+use super::permissions::PermissionOverwrite;
+use super::permissions::Permissions;
+use super::permissions::RawPermissions;
+use crate::helpers::errors::ApiError;
+use crate::helpers::errors::ApiResult;
+
+/// Parses a user id Discord sent, a malformed one means Discord answered with something unusable.
+pub fn parse_user_id(id: &str) -> ApiResult<u64> {
+    id.parse::<u64>().map_err(|_| ApiError::BadGateway {
+        message: format!("Discord returned an invalid user id `{}`.", id),
+        details: None,
+    })
+}
 
 #[warn(unused_imports)]
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
@@ -6,6 +19,8 @@ pub struct Channel {
     pub id: String,
     pub name: String,
     pub kind: ChannelKind,
+    #[serde(default)]
+    pub permission_overwrites: Vec<PermissionOverwrite>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
@@ -13,16 +28,75 @@ pub struct Member {
     pub id: String,
     pub username: String,
     pub discriminator: u16,
+    #[serde(default)]
+    pub nick: Option<String>,
+    /// Ids of the member's roles, without the implicit @everyone role.
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl Member {
+    /// The name shown in the guild, the nickname when one is set.
+    pub fn display_name(&self) -> &str {
+        self.nick.as_deref().unwrap_or(&self.username)
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct MemberPage {
+    pub members: Vec<Member>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Role {
     pub id: String,
     pub name: String,
+    #[serde(default)]
+    pub permissions: RawPermissions,
+    /// Higher roles can manage the roles below them.
+    #[serde(default)]
+    pub position: i64,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Guild {
     pub id: String,
     pub name: String,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub owner: bool,
+    #[serde(default)]
+    pub permissions: RawPermissions,
+}
+
+impl Guild {
+    /// Whether the user may change the guild's settings.
+    pub fn can_manage(&self) -> bool {
+        self.owner
+            || self
+                .permissions
+                .known()
+                .intersects(Permissions::ADMINISTRATOR | Permissions::MANAGE_GUILD)
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct InvitableGuild {
+    #[serde(flatten)]
+    pub guild: Guild,
+    /// Adds the bot to the guild with the permissions its changes need.
+    pub invite_url: String,
+}
+
+/// The user's guilds for the dashboard's guild picker.
+#[derive(serde::Serialize, Debug)]
+pub struct GuildOverview {
+    /// Guilds the user and the bot are both in.
+    pub mutual: Vec<Guild>,
+    /// Guilds the user manages where the bot isn't installed yet.
+    pub invitable: Vec<InvitableGuild>,
 }
@@ -0,0 +1,433 @@
+use bitflags::bitflags;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+use super::models::Role;
+
+bitflags! {
+    /// Discord's permission bit set, see https://discord.com/developers/docs/topics/permissions.
+    #[derive(Default)]
+    pub struct Permissions: u64 {
+        const CREATE_INSTANT_INVITE = 1 << 0;
+        const KICK_MEMBERS = 1 << 1;
+        const BAN_MEMBERS = 1 << 2;
+        const ADMINISTRATOR = 1 << 3;
+        const MANAGE_CHANNELS = 1 << 4;
+        const MANAGE_GUILD = 1 << 5;
+        const ADD_REACTIONS = 1 << 6;
+        const VIEW_AUDIT_LOG = 1 << 7;
+        const PRIORITY_SPEAKER = 1 << 8;
+        const STREAM = 1 << 9;
+        const VIEW_CHANNEL = 1 << 10;
+        const SEND_MESSAGES = 1 << 11;
+        const SEND_TTS_MESSAGES = 1 << 12;
+        const MANAGE_MESSAGES = 1 << 13;
+        const EMBED_LINKS = 1 << 14;
+        const ATTACH_FILES = 1 << 15;
+        const READ_MESSAGE_HISTORY = 1 << 16;
+        const MENTION_EVERYONE = 1 << 17;
+        const USE_EXTERNAL_EMOJIS = 1 << 18;
+        const VIEW_GUILD_INSIGHTS = 1 << 19;
+        const CONNECT = 1 << 20;
+        const SPEAK = 1 << 21;
+        const MUTE_MEMBERS = 1 << 22;
+        const DEAFEN_MEMBERS = 1 << 23;
+        const MOVE_MEMBERS = 1 << 24;
+        const USE_VAD = 1 << 25;
+        const CHANGE_NICKNAME = 1 << 26;
+        const MANAGE_NICKNAMES = 1 << 27;
+        const MANAGE_ROLES = 1 << 28;
+        const MANAGE_WEBHOOKS = 1 << 29;
+        const MANAGE_EMOJIS_AND_STICKERS = 1 << 30;
+        const USE_APPLICATION_COMMANDS = 1 << 31;
+        const REQUEST_TO_SPEAK = 1 << 32;
+        const MANAGE_EVENTS = 1 << 33;
+        const MANAGE_THREADS = 1 << 34;
+        const CREATE_PUBLIC_THREADS = 1 << 35;
+        const CREATE_PRIVATE_THREADS = 1 << 36;
+        const USE_EXTERNAL_STICKERS = 1 << 37;
+        const SEND_MESSAGES_IN_THREADS = 1 << 38;
+        const USE_EMBEDDED_ACTIVITIES = 1 << 39;
+        const MODERATE_MEMBERS = 1 << 40;
+    }
+}
+
+/// Permissions exactly as Discord sent them. Unlike `Permissions` this keeps the bits Discord
+/// added after the list above was written, so passing them on to the dashboard loses nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RawPermissions(pub u64);
+
+impl RawPermissions {
+    /// The permissions this API knows about, for checking what a member may do.
+    pub fn known(self) -> Permissions {
+        Permissions::from_bits_truncate(self.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PermissionsValue {
+    Number(u64),
+    String(String),
+}
+
+/// Older API versions send permissions as a number, newer ones as a string.
+impl<'de> Deserialize<'de> for RawPermissions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match PermissionsValue::deserialize(deserializer)? {
+            PermissionsValue::Number(bits) => Ok(RawPermissions(bits)),
+            PermissionsValue::String(bits) => bits
+                .parse::<u64>()
+                .map(RawPermissions)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Serialized as a string like Discord does, the bit set doesn't fit in a JavaScript number.
+impl Serialize for RawPermissions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+/// Drops bits this list doesn't know, use `RawPermissions` for permissions that are passed on.
+impl<'de> Deserialize<'de> for Permissions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(RawPermissions::deserialize(deserializer)?.known())
+    }
+}
+
+impl Serialize for Permissions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RawPermissions(self.bits()).serialize(serializer)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverwriteKind {
+    Role,
+    Member,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawOverwriteKind {
+    Number(u8),
+    String(String),
+}
+
+impl<'de> Deserialize<'de> for OverwriteKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match RawOverwriteKind::deserialize(deserializer)? {
+            RawOverwriteKind::Number(0) => Ok(OverwriteKind::Role),
+            RawOverwriteKind::Number(1) => Ok(OverwriteKind::Member),
+            RawOverwriteKind::String(kind) if kind == "role" => Ok(OverwriteKind::Role),
+            RawOverwriteKind::String(kind) if kind == "member" => Ok(OverwriteKind::Member),
+            _ => Err(serde::de::Error::custom(
+                "unknown permission overwrite type",
+            )),
+        }
+    }
+}
+
+impl Serialize for OverwriteKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(match self {
+            OverwriteKind::Role => 0,
+            OverwriteKind::Member => 1,
+        })
+    }
+}
+
+/// Allows and denies permissions in a channel for a role or a member.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PermissionOverwrite {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: OverwriteKind,
+    pub allow: RawPermissions,
+    pub deny: RawPermissions,
+}
+
+/// Computes a member's effective permissions the way Discord does.
+pub struct PermissionCalculator<'a> {
+    pub guild_id: &'a str,
+    /// Every role of the guild, including @everyone which shares its id with the guild.
+    pub roles: &'a [Role],
+    pub member_id: &'a str,
+    pub member_roles: &'a [String],
+    pub is_owner: bool,
+}
+
+impl<'a> PermissionCalculator<'a> {
+    /// The permissions granted by the member's roles, before any channel overwrites.
+    pub fn guild_permissions(&self) -> Permissions {
+        if self.is_owner {
+            return Permissions::all();
+        }
+
+        let permissions = self
+            .roles
+            .iter()
+            .filter(|r| r.id == self.guild_id || self.member_roles.contains(&r.id))
+            .fold(Permissions::empty(), |permissions, role| {
+                permissions | role.permissions.known()
+            });
+
+        if permissions.contains(Permissions::ADMINISTRATOR) {
+            Permissions::all()
+        } else {
+            permissions
+        }
+    }
+
+    /// The permissions in a channel: @everyone's overwrite, then those of the member's roles
+    /// combined, then the member's own overwrite.
+    pub fn channel_permissions(&self, overwrites: &[PermissionOverwrite]) -> Permissions {
+        let mut permissions = self.guild_permissions();
+        if permissions.contains(Permissions::ADMINISTRATOR) {
+            return Permissions::all();
+        }
+
+        if let Some(everyone) = overwrites.iter().find(|o| o.id == self.guild_id) {
+            permissions.remove(everyone.deny.known());
+            permissions.insert(everyone.allow.known());
+        }
+
+        let mut allow = Permissions::empty();
+        let mut deny = Permissions::empty();
+        for overwrite in overwrites
+            .iter()
+            .filter(|o| o.kind == OverwriteKind::Role && self.member_roles.contains(&o.id))
+        {
+            allow.insert(overwrite.allow.known());
+            deny.insert(overwrite.deny.known());
+        }
+        permissions.remove(deny);
+        permissions.insert(allow);
+
+        if let Some(member) = overwrites
+            .iter()
+            .find(|o| o.kind == OverwriteKind::Member && o.id == self.member_id)
+        {
+            permissions.remove(member.deny.known());
+            permissions.insert(member.allow.known());
+        }
+
+        permissions
+    }
+
+    /// Position of the member's highest role, roles can only manage roles below it.
+    pub fn highest_role_position(&self) -> i64 {
+        self.roles
+            .iter()
+            .filter(|r| self.member_roles.contains(&r.id))
+            .map(|r| r.position)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD_ID: &str = "1";
+    const MEMBER_ID: &str = "100";
+
+    fn role(id: &str, permissions: Permissions, position: i64) -> Role {
+        Role {
+            id: id.into(),
+            name: format!("role {}", id),
+            permissions: RawPermissions(permissions.bits()),
+            position,
+        }
+    }
+
+    fn roles() -> Vec<Role> {
+        vec![
+            role(
+                GUILD_ID,
+                Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+                0,
+            ),
+            role("2", Permissions::MANAGE_MESSAGES, 1),
+            role("3", Permissions::empty(), 5),
+            role("4", Permissions::ADMINISTRATOR, 2),
+        ]
+    }
+
+    fn overwrite(
+        id: &str,
+        kind: OverwriteKind,
+        allow: Permissions,
+        deny: Permissions,
+    ) -> PermissionOverwrite {
+        PermissionOverwrite {
+            id: id.into(),
+            kind,
+            allow: RawPermissions(allow.bits()),
+            deny: RawPermissions(deny.bits()),
+        }
+    }
+
+    fn calculator<'a>(roles: &'a [Role], member_roles: &'a [String]) -> PermissionCalculator<'a> {
+        PermissionCalculator {
+            guild_id: GUILD_ID,
+            roles,
+            member_id: MEMBER_ID,
+            member_roles,
+            is_owner: false,
+        }
+    }
+
+    fn member_roles(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn owner_gets_every_permission() {
+        let roles = roles();
+        let member_roles = member_roles(&[]);
+        let mut owner = calculator(&roles, &member_roles);
+        owner.is_owner = true;
+
+        assert_eq!(owner.guild_permissions(), Permissions::all());
+    }
+
+    #[test]
+    fn roles_and_everyone_are_combined() {
+        let roles = roles();
+        let member_roles = member_roles(&["2"]);
+
+        assert_eq!(
+            calculator(&roles, &member_roles).guild_permissions(),
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES | Permissions::MANAGE_MESSAGES
+        );
+    }
+
+    #[test]
+    fn administrator_ignores_overwrites() {
+        let roles = roles();
+        let member_roles = member_roles(&["4"]);
+        let overwrites = [overwrite(
+            MEMBER_ID,
+            OverwriteKind::Member,
+            Permissions::empty(),
+            Permissions::all(),
+        )];
+        let calculator = calculator(&roles, &member_roles);
+
+        assert_eq!(calculator.guild_permissions(), Permissions::all());
+        assert_eq!(
+            calculator.channel_permissions(&overwrites),
+            Permissions::all()
+        );
+    }
+
+    #[test]
+    fn everyone_overwrite_comes_before_role_overwrites() {
+        let roles = roles();
+        let overwrites = [
+            overwrite(
+                GUILD_ID,
+                OverwriteKind::Role,
+                Permissions::ATTACH_FILES,
+                Permissions::SEND_MESSAGES,
+            ),
+            overwrite(
+                "2",
+                OverwriteKind::Role,
+                Permissions::SEND_MESSAGES,
+                Permissions::ATTACH_FILES,
+            ),
+        ];
+        let with_role = member_roles(&["2"]);
+        let without_role = member_roles(&[]);
+
+        let permissions = calculator(&roles, &with_role).channel_permissions(&overwrites);
+        assert!(permissions.contains(Permissions::SEND_MESSAGES));
+        assert!(!permissions.contains(Permissions::ATTACH_FILES));
+
+        let permissions = calculator(&roles, &without_role).channel_permissions(&overwrites);
+        assert!(!permissions.contains(Permissions::SEND_MESSAGES));
+        assert!(permissions.contains(Permissions::ATTACH_FILES));
+    }
+
+    #[test]
+    fn role_allows_beat_role_denies() {
+        let roles = roles();
+        let member_roles = member_roles(&["2", "3"]);
+        let overwrites = [
+            overwrite(
+                "2",
+                OverwriteKind::Role,
+                Permissions::empty(),
+                Permissions::EMBED_LINKS | Permissions::VIEW_CHANNEL,
+            ),
+            overwrite(
+                "3",
+                OverwriteKind::Role,
+                Permissions::EMBED_LINKS,
+                Permissions::empty(),
+            ),
+        ];
+
+        let permissions = calculator(&roles, &member_roles).channel_permissions(&overwrites);
+        assert!(permissions.contains(Permissions::EMBED_LINKS));
+        assert!(!permissions.contains(Permissions::VIEW_CHANNEL));
+    }
+
+    #[test]
+    fn member_overwrite_wins() {
+        let roles = roles();
+        let member_roles = member_roles(&["2"]);
+        let overwrites = [
+            overwrite(
+                "2",
+                OverwriteKind::Role,
+                Permissions::EMBED_LINKS,
+                Permissions::empty(),
+            ),
+            overwrite(
+                MEMBER_ID,
+                OverwriteKind::Member,
+                Permissions::VIEW_AUDIT_LOG,
+                Permissions::EMBED_LINKS,
+            ),
+        ];
+
+        let permissions = calculator(&roles, &member_roles).channel_permissions(&overwrites);
+        assert!(!permissions.contains(Permissions::EMBED_LINKS));
+        assert!(permissions.contains(Permissions::VIEW_AUDIT_LOG));
+    }
+
+    #[test]
+    fn highest_role_position_of_the_member() {
+        let roles = roles();
+        let some_roles = member_roles(&["2", "3"]);
+        let no_roles = member_roles(&[]);
+
+        assert_eq!(calculator(&roles, &some_roles).highest_role_position(), 5);
+        assert_eq!(calculator(&roles, &no_roles).highest_role_position(), 0);
+    }
+
+    #[test]
+    fn raw_permissions_keep_unknown_bits() {
+        let bits = (1u64 << 50) | Permissions::ADMINISTRATOR.bits();
+        let from_string: RawPermissions =
+            serde_json::from_value(serde_json::json!(bits.to_string())).unwrap();
+        let from_number: RawPermissions = serde_json::from_value(serde_json::json!(bits)).unwrap();
+
+        assert_eq!(from_string, RawPermissions(bits));
+        assert_eq!(from_number, RawPermissions(bits));
+        assert_eq!(
+            serde_json::to_value(from_string).unwrap(),
+            serde_json::json!(bits.to_string())
+        );
+        assert_eq!(from_string.known(), Permissions::ADMINISTRATOR);
+    }
+}
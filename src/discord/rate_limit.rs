@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use actix_web::rt::time::delay_for;
+use reqwest::header::HeaderMap;
+
+use super::base_api::HttpMethod;
+
+/// Segments whose id is a "major parameter", Discord keeps separate buckets per value of these.
+const MAJOR_PARAMETERS: [&str; 3] = ["guilds", "channels", "webhooks"];
+
+/// A rate limit route, e.g. `Get /guilds/123/members/:id` with `guilds/123` as major parameter.
+pub struct Route {
+    pub key: String,
+    pub major: String,
+}
+
+impl Route {
+    pub fn new(method: &HttpMethod, endpoint: &str) -> Self {
+        let segments: Vec<&str> = endpoint.split('/').collect();
+        let mut major = String::new();
+        let mut path: Vec<&str> = Vec::with_capacity(segments.len());
+        for (index, segment) in segments.iter().enumerate() {
+            let is_id = !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit());
+            if !is_id {
+                path.push(segment);
+                continue;
+            }
+
+            let parent = if index > 0 { segments[index - 1] } else { "" };
+            if major.is_empty() && index == 2 && MAJOR_PARAMETERS.contains(&parent) {
+                major = format!("{}/{}", parent, segment);
+                path.push(segment);
+            } else {
+                path.push(":id");
+            }
+        }
+
+        Self {
+            key: format!("{:?} {}", method, path.join("/")),
+            major,
+        }
+    }
+}
+
+struct Bucket {
+    remaining: u64,
+    reset_at: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    /// Route key to the bucket hash Discord reported for it.
+    routes: HashMap<String, String>,
+    /// Bucket hash combined with the major parameter to the bucket's current window.
+    buckets: HashMap<String, Bucket>,
+    global_reset_at: Option<Instant>,
+}
+
+impl State {
+    fn get_bucket_key(&self, route: &Route) -> Option<String> {
+        let hash = self.routes.get(&route.key)?;
+        Some(format!("{}:{}", hash, route.major))
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+fn get_header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
+
+impl RateLimiter {
+    /// Reserves a request in the route's bucket, returns how long to wait when it is exhausted.
+    fn reserve(&self, route: &Route) -> Option<Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(reset_at) = state.global_reset_at {
+            if reset_at > now {
+                return Some(reset_at - now);
+            }
+            state.global_reset_at = None;
+        }
+
+        let bucket_key = state.get_bucket_key(route)?;
+        let bucket = state.buckets.get_mut(&bucket_key)?;
+        if bucket.reset_at <= now {
+            state.buckets.remove(&bucket_key);
+            return None;
+        }
+        if bucket.remaining == 0 {
+            return Some(bucket.reset_at - now);
+        }
+        bucket.remaining -= 1;
+        None
+    }
+
+    /// Waits until a request on the route can be sent without hitting a known limit.
+    pub async fn acquire(&self, route: &Route) {
+        while let Some(wait) = self.reserve(route) {
+            delay_for(wait).await;
+        }
+    }
+
+    /// Records the bucket state from the `X-RateLimit-*` headers of a response.
+    pub fn update(&self, route: &Route, headers: &HeaderMap) {
+        let hash = match get_header(headers, "x-ratelimit-bucket") {
+            Some(hash) => hash.to_string(),
+            None => return,
+        };
+        let remaining =
+            get_header(headers, "x-ratelimit-remaining").and_then(|v| v.parse::<u64>().ok());
+        let reset_after =
+            get_header(headers, "x-ratelimit-reset-after").and_then(|v| v.parse::<f64>().ok());
+
+        let mut state = self.state.lock().unwrap();
+        state.routes.insert(route.key.clone(), hash);
+        if let (Some(remaining), Some(reset_after), Some(bucket_key)) =
+            (remaining, reset_after, state.get_bucket_key(route))
+        {
+            state.buckets.insert(
+                bucket_key,
+                Bucket {
+                    remaining,
+                    reset_at: Instant::now() + Duration::from_secs_f64(reset_after),
+                },
+            );
+        }
+    }
+
+    /// Blocks every route until the global limit resets.
+    pub fn set_global(&self, retry_after: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.global_reset_at = Some(Instant::now() + retry_after);
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct RateLimitResponse {
+    retry_after: f64,
+    #[serde(default)]
+    global: bool,
+}
+
+pub struct RetryAfter {
+    pub duration: Duration,
+    pub global: bool,
+}
+
+impl RetryAfter {
+    /// Reads how long to back off from a 429 response, preferring the precise JSON body.
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let global_header = get_header(response.headers(), "x-ratelimit-global").is_some();
+        let header_seconds =
+            get_header(response.headers(), "retry-after").and_then(|v| v.parse::<f64>().ok());
+
+        match response.json::<RateLimitResponse>().await {
+            Ok(body) => Self {
+                duration: Duration::from_secs_f64(body.retry_after.max(0.0)),
+                global: body.global || global_header,
+            },
+            Err(_) => Self {
+                duration: Duration::from_secs_f64(header_seconds.unwrap_or(1.0).max(0.0)),
+                global: global_header,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_keeps_the_major_parameter() {
+        let route = Route::new(&HttpMethod::Get, "/guilds/123/members");
+        assert_eq!(route.key, "Get /guilds/123/members");
+        assert_eq!(route.major, "guilds/123");
+
+        let route = Route::new(&HttpMethod::Delete, "/channels/456");
+        assert_eq!(route.key, "Delete /channels/456");
+        assert_eq!(route.major, "channels/456");
+    }
+
+    #[test]
+    fn route_replaces_other_ids() {
+        let route = Route::new(&HttpMethod::Put, "/guilds/123/members/456/roles/789");
+        assert_eq!(route.key, "Put /guilds/123/members/:id/roles/:id");
+        assert_eq!(route.major, "guilds/123");
+
+        let route = Route::new(&HttpMethod::Get, "/users/123");
+        assert_eq!(route.key, "Get /users/:id");
+        assert_eq!(route.major, "");
+    }
+
+    #[test]
+    fn route_without_ids() {
+        let route = Route::new(&HttpMethod::Get, "/users/@me/guilds");
+        assert_eq!(route.key, "Get /users/@me/guilds");
+        assert_eq!(route.major, "");
+    }
+
+    #[test]
+    fn route_separates_methods_and_major_parameters() {
+        let get = Route::new(&HttpMethod::Get, "/channels/1/messages/2");
+        let delete = Route::new(&HttpMethod::Delete, "/channels/1/messages/3");
+        let other_channel = Route::new(&HttpMethod::Get, "/channels/4/messages/2");
+        assert_ne!(get.key, delete.key);
+        assert_eq!(get.major, delete.major);
+        assert_ne!(get.major, other_channel.major);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::config::Config;
+use crate::helpers::caching::discord::GuildResource;
+use crate::helpers::errors::ApiError;
+use crate::helpers::repositories::discord::refresh_guild;
 use crate::helpers::repositories::discord::ChannelRepository;
 use crate::helpers::repositories::discord::ChannelRepositoryOptions;
 use crate::helpers::repositories::discord::MemberRepository;
@@ -6,21 +10,66 @@ use crate::helpers::repositories::discord::RoleRepository;
 
 use crate::helpers::repositories::discord::SharedRepositoryOptions;
 use crate::helpers::validator::get_allowed_guilds;
-use crate::helpers::validator::parse_access_token;
+use crate::helpers::validator::get_guild_lists;
+use crate::helpers::validator::AccessLevel;
 use crate::helpers::validator::Validator;
+use crate::oauth::routes::get_bot_invite_url;
+use crate::oauth::session::get_access_token;
 
 use super::calls::ChannelKind;
+use super::models::GuildOverview;
+use super::models::InvitableGuild;
+use super::models::Member;
+use super::models::MemberPage;
 use actix_web::get;
+use actix_web::post;
+use actix_web::web;
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
+use actix_web::ResponseError;
 
 #[get("/get_mutual_guilds")]
 pub async fn get_mutual_guilds(req: HttpRequest) -> HttpResponse {
-    let access_token = parse_access_token(&req);
+    let access_token = match get_access_token(&req).await {
+        Ok(access_token) => access_token,
+        Err(err) => return err.error_response(),
+    };
 
-    match get_allowed_guilds(&access_token.as_ref().unwrap()).await {
+    match get_allowed_guilds(&access_token).await {
         Ok(guilds) => HttpResponse::Ok().json(guilds),
-        Err(_) => HttpResponse::BadRequest().finish(),
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Mutual guilds plus the guilds the user manages where the bot still has to be invited.
+#[get("/get_guilds")]
+pub async fn get_guilds(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    let access_token = match get_access_token(&req).await {
+        Ok(access_token) => access_token,
+        Err(err) => return err.error_response(),
+    };
+
+    match get_guild_lists(&access_token).await {
+        Ok(lists) => {
+            let invitable = lists
+                .without_bot
+                .into_iter()
+                .filter(|g| g.can_manage())
+                .filter_map(|guild| {
+                    let guild_id = guild.id.parse::<u64>().ok()?;
+                    Some(InvitableGuild {
+                        guild,
+                        invite_url: get_bot_invite_url(&config, guild_id),
+                    })
+                })
+                .collect();
+
+            HttpResponse::Ok().json(GuildOverview {
+                mutual: lists.mutual,
+                invitable,
+            })
+        }
+        Err(err) => err.error_response(),
     }
 }
 
@@ -35,10 +84,10 @@ pub async fn get_all_text_channels(req: HttpRequest) -> HttpResponse {
             .await
             {
                 Ok(channels) => HttpResponse::Ok().json(channels),
-                Err(err) => HttpResponse::BadRequest().body(err),
+                Err(err) => err.error_response(),
             }
         }
-        Err(err) => HttpResponse::BadRequest().body(err),
+        Err(err) => err.error_response(),
     }
 }
 
@@ -48,22 +97,142 @@ pub async fn get_all_roles(req: HttpRequest) -> HttpResponse {
         Ok(validation) => {
             match RoleRepository::get(&SharedRepositoryOptions(validation.guild_id)).await {
                 Ok(roles) => HttpResponse::Ok().json(roles),
-                Err(err) => HttpResponse::BadRequest().body(err),
+                Err(err) => err.error_response(),
+            }
+        }
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Fetches the guild's channels, roles or members from Discord again, for when something changed
+/// outside the dashboard. The next read returns the fresh data.
+#[post("/{guild_id}/refresh/{resource}")]
+pub async fn refresh_guild_cache(req: HttpRequest) -> HttpResponse {
+    let resource = match req
+        .match_info()
+        .get("resource")
+        .and_then(|r| r.parse::<GuildResource>().ok())
+    {
+        Some(resource) => resource,
+        None => {
+            return ApiError::BadRequest(
+                "Unknown resource, expected channels, roles or members.".into(),
+            )
+            .error_response()
+        }
+    };
+
+    match Validator::new()
+        .require(AccessLevel::Manager)
+        .validate(&req)
+        .await
+    {
+        Ok(validation) => match refresh_guild(validation.guild_id, resource).await {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(err) => err.error_response(),
+        },
+        Err(err) => err.error_response(),
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberSort {
+    #[default]
+    Username,
+    DisplayName,
+    Id,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct MemberListQuery {
+    /// Case insensitive substring of the username or nickname.
+    search: Option<String>,
+    /// Only members that have this role id.
+    role: Option<String>,
+    #[serde(default)]
+    sort: MemberSort,
+    #[serde(default)]
+    order: SortOrder,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+impl MemberListQuery {
+    const DEFAULT_LIMIT: usize = 100;
+    const MAX_LIMIT: usize = 1000;
+
+    fn apply(self, members: Vec<Member>) -> MemberPage {
+        let search = self
+            .search
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty());
+
+        let mut members: Vec<Member> = members
+            .into_iter()
+            .filter(|m| match &self.role {
+                Some(role) => m.roles.contains(role),
+                None => true,
+            })
+            .filter(|m| match &search {
+                Some(search) => {
+                    m.username.to_lowercase().contains(search)
+                        || m.nick
+                            .as_ref()
+                            .is_some_and(|n| n.to_lowercase().contains(search))
+                }
+                None => true,
+            })
+            .collect();
+
+        match self.sort {
+            MemberSort::Username => members.sort_by_cached_key(|m| m.username.to_lowercase()),
+            MemberSort::DisplayName => {
+                members.sort_by_cached_key(|m| m.display_name().to_lowercase())
             }
+            MemberSort::Id => members.sort_by_cached_key(|m| m.id.parse::<u64>().unwrap_or(0)),
+        }
+        if self.order == SortOrder::Desc {
+            members.reverse();
+        }
+
+        let total = members.len();
+        let offset = self.offset.unwrap_or(0);
+        let limit = self
+            .limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT);
+
+        MemberPage {
+            members: members.into_iter().skip(offset).take(limit).collect(),
+            total,
+            offset,
+            limit,
         }
-        Err(err) => HttpResponse::BadRequest().body(err),
     }
 }
 
 #[get("/{guild_id}/get_all_members")]
-pub async fn get_all_members(req: HttpRequest) -> HttpResponse {
-    match Validator::new().validate(&req).await {
+pub async fn get_all_members(req: HttpRequest, query: web::Query<MemberListQuery>) -> HttpResponse {
+    match Validator::new()
+        .require(AccessLevel::Manager)
+        .validate(&req)
+        .await
+    {
         Ok(validation) => {
             match MemberRepository::get(&SharedRepositoryOptions(validation.guild_id)).await {
-                Ok(members) => HttpResponse::Ok().json(members),
-                Err(err) => HttpResponse::BadRequest().body(err),
+                Ok(members) => HttpResponse::Ok().json(query.into_inner().apply(members)),
+                Err(err) => err.error_response(),
             }
         }
-        Err(err) => HttpResponse::BadRequest().body(err),
+        Err(err) => err.error_response(),
     }
 }
@@ -1,75 +1,330 @@
+use std::time::Duration;
+
+use actix_web::web;
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use r2d2::Pool;
 use redis::Commands;
 use redis::Connection;
+use redis::ConnectionLike;
 use redis::FromRedisValue;
+use redis::RedisError;
+use redis::RedisResult;
 use redis::ToRedisArgs;
 
-pub fn get_connection_redis() -> Result<Connection, &'static str> {
-    let client =
-        redis::Client::open("redis://127.0.0.1/").map_err(|_| "Failed to get redis client")?;
-    client
-        .get_connection()
-        .map_err(|_| "Failed to get Redis connection")
+use crate::config::CacheBackend;
+use crate::config::Config;
+use crate::config::RedisConfig;
+use crate::helpers::errors::ApiError;
+use crate::helpers::errors::ApiResult;
+
+use super::memory::MemoryCache;
+
+static CACHE_STORE: OnceCell<CacheStore> = OnceCell::new();
+
+/// A pooled connection, poisoned once a command timed out since its reply may still arrive and
+/// would then be read as the reply to the next command.
+pub struct RedisConnection {
+    connection: Connection,
+    poisoned: bool,
 }
 
-pub trait CacheKey {
-    const KEY: &'static str;
-    fn get_key(&self) -> String;
+pub struct RedisConnectionManager {
+    client: redis::Client,
+    timeout: Duration,
 }
 
-pub trait Cache<D: CacheKey, T: ToRedisArgs + FromRedisValue> {
-    fn get(key: D) -> Option<T> {
-        let mut connection = get_connection_redis().ok()?;
-        let value: T = connection.get(key.get_key()).ok()?;
-        Some(value)
+impl r2d2::ManageConnection for RedisConnectionManager {
+    type Connection = RedisConnection;
+    type Error = RedisError;
+
+    fn connect(&self) -> Result<RedisConnection, RedisError> {
+        let connection = self.client.get_connection_with_timeout(self.timeout)?;
+        connection.set_read_timeout(Some(self.timeout))?;
+        connection.set_write_timeout(Some(self.timeout))?;
+        Ok(RedisConnection {
+            connection,
+            poisoned: false,
+        })
+    }
+
+    fn is_valid(&self, connection: &mut RedisConnection) -> Result<(), RedisError> {
+        redis::cmd("PING").query(&mut connection.connection)
+    }
+
+    fn has_broken(&self, connection: &mut RedisConnection) -> bool {
+        connection.poisoned || !connection.connection.is_open()
+    }
+}
+
+#[derive(Clone)]
+pub struct RedisPool(Pool<RedisConnectionManager>);
+
+impl RedisPool {
+    /// Fails when Redis can't be reached within the configured timeout.
+    pub fn connect(config: &RedisConfig) -> Result<Self, String> {
+        let client = redis::Client::open(config.url.as_str())
+            .map_err(|e| format!("Failed to get redis client: {}", e))?;
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let pool = Pool::builder()
+            .max_size(config.pool_size)
+            .connection_timeout(timeout)
+            .build(RedisConnectionManager { client, timeout })
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        Ok(Self(pool))
+    }
+
+    /// Runs `f` with a pooled connection inside `web::block`, since the pool hands out
+    /// blocking connections. Waits at most the configured timeout for a free one.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Connection) -> RedisResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.0.clone();
+        web::block(move || {
+            let mut connection = pool.get().map_err(|e| format!("{}", e))?;
+            f(&mut connection.connection).map_err(|e| {
+                if e.is_timeout() {
+                    connection.poisoned = true;
+                }
+                format!("{}", e)
+            })
+        })
+        .await
+        .map_err(|e| format!("{}", e))
+    }
+}
+
+/// Where the caches keep their values, chosen by `CACHE_BACKEND`.
+#[derive(Clone)]
+pub enum CacheStore {
+    Redis(RedisPool),
+    Memory(MemoryCache),
+}
+
+impl CacheStore {
+    /// Fails when the Redis backend is chosen and Redis can't be reached.
+    pub fn connect(config: &Config) -> Result<Self, String> {
+        match config.cache.backend {
+            CacheBackend::Redis => Ok(CacheStore::Redis(RedisPool::connect(&config.redis)?)),
+            CacheBackend::Memory => Ok(CacheStore::Memory(MemoryCache::new(
+                config.cache.memory_capacity,
+            ))),
+        }
+    }
+
+    /// Sets the store behind `get_cache_store`, which the `Cache` trait's associated functions
+    /// read since they have no request to take it from. A store installed earlier wins.
+    pub fn install(self) -> &'static CacheStore {
+        CACHE_STORE.get_or_init(|| self)
     }
 
-    fn set(key: D, value: &T) -> bool {
-        if let Ok(mut connection) = get_connection_redis() {
-            let full_key = key.get_key();
-            let result: Result<(), _> = connection.set(&full_key, value);
-            if let Some(expire) = Self::get_expire(&key) {
-                let _: Result<(), _> = connection.expire(&full_key, expire);
+    pub async fn get(&self, key: String) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            CacheStore::Redis(pool) => pool.run(move |connection| connection.get(key)).await,
+            CacheStore::Memory(memory) => Ok(memory.get(&key)),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        expire: Option<usize>,
+    ) -> Result<(), String> {
+        match self {
+            CacheStore::Redis(pool) => {
+                pool.run(move |connection| match expire {
+                    Some(expire) => connection.set_ex(key, value, expire),
+                    None => connection.set(key, value),
+                })
+                .await
+            }
+            CacheStore::Memory(memory) => {
+                memory.set(key, value, expire);
+                Ok(())
             }
-            result.is_ok()
-        } else {
-            false
         }
     }
 
-    fn get_expire(_: &D) -> Option<usize> {
-        None
+    /// Returns whether the key existed.
+    pub async fn delete(&self, key: String) -> Result<bool, String> {
+        match self {
+            CacheStore::Redis(pool) => {
+                let keys = [empty_list_key(&key), key];
+                let deleted = pool
+                    .run(move |connection| connection.del::<_, usize>(&keys))
+                    .await?;
+                Ok(deleted > 0)
+            }
+            CacheStore::Memory(memory) => Ok(memory.delete(&key)),
+        }
     }
 
-    fn get_vec<F: FromRedisValue>(key: D) -> Option<Vec<F>> {
-        let mut connection = get_connection_redis().ok()?;
-        let full_key = Self::get_full_key(&key);
-        if connection.exists(&full_key).ok()? {
-            let value: Vec<F> = connection.lrange(&full_key, 0, 100).ok()?;
-            Some(value)
-        } else {
-            None
+    /// The whole list in order, `None` when the key doesn't exist.
+    pub async fn get_list(&self, key: String) -> Result<Option<Vec<Vec<u8>>>, String> {
+        match self {
+            CacheStore::Redis(pool) => {
+                let (exists, empty, items): (bool, bool, Vec<Vec<u8>>) = pool
+                    .run(move |connection| {
+                        redis::pipe()
+                            .atomic()
+                            .exists(&key)
+                            .exists(empty_list_key(&key))
+                            .lrange(&key, 0, -1)
+                            .query(connection)
+                    })
+                    .await?;
+                Ok(Some(items).filter(|_| exists || empty))
+            }
+            CacheStore::Memory(memory) => Ok(memory.get_list(&key)),
         }
     }
 
-    fn set_vec<F: ToRedisArgs>(key: D, value: &Vec<F>) -> bool {
-        if let Ok(mut connection) = get_connection_redis() {
-            let full_key = Self::get_full_key(&key);
-            let mut all_ok: bool = true;
-            for item in value.iter() {
-                let result: Result<(), _> = connection.lpush(&full_key, item);
-                if result.is_err() {
-                    all_ok = false;
-                }
+    /// Replaces the list in one transaction, so readers see either the old or the new snapshot
+    /// and refreshes never pile up duplicates. Redis can't store an empty list, so one is kept
+    /// as a marker next to the key instead.
+    pub async fn set_list(
+        &self,
+        key: String,
+        items: Vec<Vec<u8>>,
+        expire: Option<usize>,
+    ) -> Result<(), String> {
+        match self {
+            CacheStore::Redis(pool) => {
+                pool.run(move |connection| {
+                    let empty_key = empty_list_key(&key);
+                    let mut pipe = redis::pipe();
+                    pipe.atomic().del(&[&key, &empty_key]).ignore();
+                    let stored_key = if items.is_empty() {
+                        pipe.set(&empty_key, 1).ignore();
+                        empty_key
+                    } else {
+                        pipe.rpush(&key, items).ignore();
+                        key
+                    };
+                    if let Some(expire) = expire {
+                        pipe.expire(&stored_key, expire).ignore();
+                    }
+                    pipe.query(connection)
+                })
+                .await
+            }
+            CacheStore::Memory(memory) => {
+                memory.set_list(key, items, expire);
+                Ok(())
             }
+        }
+    }
 
-            if let Some(expire) = Self::get_expire(&key) {
-                let _: Result<(), _> = connection.expire(&full_key, expire);
+    /// Moves `from` to `to` with its expiry unless `to` exists, returns whether it moved.
+    pub async fn rename_nx(&self, from: String, to: String) -> Result<bool, String> {
+        match self {
+            CacheStore::Redis(pool) => {
+                pool.run(move |connection| {
+                    redis::cmd("RENAMENX").arg(from).arg(to).query(connection)
+                })
+                .await
             }
-            all_ok
-        } else {
-            false
+            CacheStore::Memory(memory) => Ok(memory.rename_nx(&from, &to)),
         }
     }
+}
+
+/// Marks a cached list as empty, Redis drops a list together with its last item.
+fn empty_list_key(key: &str) -> String {
+    format!("{}:empty", key)
+}
+
+/// Installs a memory store, for tests that reach the caches.
+#[cfg(test)]
+pub fn install_test_store() -> &'static CacheStore {
+    CacheStore::Memory(MemoryCache::new(1024)).install()
+}
+
+pub fn get_cache_store() -> &'static CacheStore {
+    CACHE_STORE
+        .get()
+        .expect("The cache store is installed before the server starts")
+}
+
+/// Kept apart from a missing entry, so e.g. a Redis outage doesn't look like an expired session.
+pub(super) fn unavailable(err: String) -> ApiError {
+    ApiError::Unavailable(format!("The cache could not be reached: {}", err))
+}
+
+fn decode<T: FromRedisValue>(bytes: Vec<u8>) -> Option<T> {
+    T::from_redis_value(&redis::Value::Data(bytes)).ok()
+}
+
+fn encode<T: ToRedisArgs>(value: &T) -> Vec<u8> {
+    value.to_redis_args().concat()
+}
+
+pub trait CacheKey {
+    const KEY: &'static str;
+    fn get_key(&self) -> String;
+}
+
+#[async_trait]
+pub trait Cache<D, T>
+where
+    D: CacheKey + Send + Sync + 'static,
+    T: ToRedisArgs + FromRedisValue + Send + Sync + 'static,
+{
+    /// An entry that can't be decoded counts as missing.
+    async fn get(key: D) -> ApiResult<Option<T>> {
+        let bytes = get_cache_store()
+            .get(key.get_key())
+            .await
+            .map_err(unavailable)?;
+        Ok(bytes.and_then(decode))
+    }
+
+    async fn set(key: D, value: &T) -> bool {
+        let expire = Self::get_expire(&key);
+        get_cache_store()
+            .set(key.get_key(), encode(value), expire)
+            .await
+            .is_ok()
+    }
+
+    /// Returns whether the key existed, deleting is atomic so only one caller gets `true`.
+    async fn delete(key: D) -> ApiResult<bool> {
+        get_cache_store()
+            .delete(Self::get_full_key(&key))
+            .await
+            .map_err(unavailable)
+    }
+
+    fn get_expire(_: &D) -> Option<usize> {
+        None
+    }
+
+    /// The whole list in the order it was cached, `None` when nothing is cached.
+    async fn get_vec<F>(key: D) -> ApiResult<Option<Vec<F>>>
+    where
+        F: FromRedisValue + Send + 'static,
+    {
+        let items = get_cache_store()
+            .get_list(Self::get_full_key(&key))
+            .await
+            .map_err(unavailable)?;
+        Ok(items.and_then(|items| items.into_iter().map(decode).collect()))
+    }
+
+    async fn set_vec<F>(key: D, value: &[F]) -> bool
+    where
+        F: ToRedisArgs + Sync,
+    {
+        let items = value.iter().map(encode).collect();
+        let expire = Self::get_expire(&key);
+        get_cache_store()
+            .set_list(Self::get_full_key(&key), items, expire)
+            .await
+            .is_ok()
+    }
 
     fn get_full_key(key: &D) -> String {
         if let Some(value) = Self::get_additional_namespace() {
@@ -83,3 +338,102 @@ pub trait Cache<D: CacheKey, T: ToRedisArgs + FromRedisValue> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ListKey(u64);
+
+    impl CacheKey for ListKey {
+        const KEY: &'static str = "test";
+
+        fn get_key(&self) -> String {
+            format!("{}:{}", Self::KEY, self.0)
+        }
+    }
+
+    struct ListCache;
+
+    impl Cache<ListKey, String> for ListCache {
+        fn get_additional_namespace() -> Option<String> {
+            Some("list".into())
+        }
+    }
+
+    #[test]
+    fn set_vec_replaces_the_list() {
+        install_test_store();
+        let cached = actix_web::rt::System::new("test").block_on(async {
+            let first = ["a", "b", "c"].map(String::from);
+            let second = ["z", "y"].map(String::from);
+            assert!(ListCache::set_vec(ListKey(1), &first).await);
+            assert!(ListCache::set_vec(ListKey(1), &second).await);
+            ListCache::get_vec::<String>(ListKey(1)).await
+        });
+
+        assert_eq!(cached.unwrap(), Some(vec!["z".into(), "y".into()]));
+    }
+
+    #[test]
+    fn set_vec_keeps_an_empty_list() {
+        install_test_store();
+        let cached = actix_web::rt::System::new("test").block_on(async {
+            assert!(ListCache::set_vec::<String>(ListKey(2), &[]).await);
+            ListCache::get_vec::<String>(ListKey(2)).await
+        });
+
+        assert_eq!(cached.unwrap(), Some(Vec::new()));
+    }
+
+    /// The Redis at `REDIS_URL`, `None` when it can't be reached so the test is skipped.
+    fn redis_store() -> Option<CacheStore> {
+        let config = RedisConfig {
+            url: std::env::var("REDIS_URL").unwrap_or_else(|_| RedisConfig::default().url),
+            pool_size: 1,
+            timeout_ms: 500,
+        };
+        match RedisPool::connect(&config) {
+            Ok(pool) => Some(CacheStore::Redis(pool)),
+            Err(err) => {
+                eprintln!("Skipping the Redis test: {}", err);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn redis_set_list_replaces_the_list() {
+        let store = match redis_store() {
+            Some(store) => store,
+            None => return,
+        };
+        let key = format!("test:{}", crate::helpers::crypto::random_token());
+        let items = |values: &[&str]| -> Vec<Vec<u8>> {
+            values.iter().map(|v| v.as_bytes().to_vec()).collect()
+        };
+        let (first, replaced, emptied, deleted, gone) = {
+            let (first, second) = (items(&["a", "b", "c"]), items(&["z", "y"]));
+            actix_web::rt::System::new("test").block_on(async move {
+                store.set_list(key.clone(), first, Some(60)).await.unwrap();
+                let first = store.get_list(key.clone()).await.unwrap();
+                store.set_list(key.clone(), second, Some(60)).await.unwrap();
+                let replaced = store.get_list(key.clone()).await.unwrap();
+                store
+                    .set_list(key.clone(), Vec::new(), Some(60))
+                    .await
+                    .unwrap();
+                let emptied = store.get_list(key.clone()).await.unwrap();
+                let deleted = store.delete(key.clone()).await.unwrap();
+                let gone = store.get_list(key).await.unwrap();
+                (first, replaced, emptied, deleted, gone)
+            })
+        };
+
+        assert_eq!(first, Some(items(&["a", "b", "c"])));
+        assert_eq!(replaced, Some(items(&["z", "y"])));
+        assert_eq!(emptied, Some(Vec::new()));
+        assert!(deleted);
+        assert_eq!(gone, None);
+    }
+}
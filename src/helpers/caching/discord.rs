@@ -1,17 +1,33 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+use async_trait::async_trait;
 use redis::ErrorKind;
 use redis::FromRedisValue;
 use redis::RedisError;
 use redis::ToRedisArgs;
+use serde::Deserialize;
+use serde::Serialize;
+use strum_macros::Display;
+use strum_macros::EnumString;
 
+use crate::config::get_config;
 use crate::discord::models::Channel;
 use crate::discord::models::Guild;
 use crate::discord::models::Member;
 use crate::discord::models::Role;
+use crate::helpers::crypto::keyed_hash;
+use crate::helpers::errors::ApiResult;
+use crate::oauth::models::StoredSession;
 use crate::redis_struct;
 
+use super::base::get_cache_store;
+use super::base::unavailable;
 use super::base::Cache;
 use super::base::CacheKey;
 
+/// Identifies an access token in cache keys without storing the token itself.
 #[derive(Debug)]
 pub struct AccessTokenHash {
     pub hash: String,
@@ -19,19 +35,29 @@ pub struct AccessTokenHash {
 }
 
 impl AccessTokenHash {
-    pub fn new(hash: &str) -> Self {
+    pub fn new(access_token: &str) -> Self {
         Self {
-            hash: hash.into(),
+            hash: keyed_hash(access_token),
             expires_in: None,
         }
     }
 
-    pub fn new_with_expires_in(hash: &str, expires_in: usize) -> Self {
+    pub fn new_with_expires_in(access_token: &str, expires_in: usize) -> Self {
         Self {
-            hash: hash.into(),
+            hash: keyed_hash(access_token),
             expires_in: Some(expires_in),
         }
     }
+
+    /// The key entries were stored under before the keyed hash, a 64 bit `DefaultHasher` hash.
+    fn legacy(access_token: &str) -> Self {
+        let mut hasher = DefaultHasher::new();
+        access_token.hash(&mut hasher);
+        Self {
+            hash: hasher.finish().to_string(),
+            expires_in: None,
+        }
+    }
 }
 
 impl CacheKey for AccessTokenHash {
@@ -49,6 +75,37 @@ impl Cache<AccessTokenHash, u64> for UserIdCache {
     }
 }
 
+impl UserIdCache {
+    /// Looks up the user of an access token. An entry still stored under the legacy key is moved
+    /// to the keyed hash with its expiry intact, so existing logins keep working.
+    pub async fn get_for_token(access_token: &str) -> ApiResult<Option<u64>> {
+        if let Some(user_id) = Self::get(AccessTokenHash::new(access_token)).await? {
+            return Ok(Some(user_id));
+        }
+
+        let key = AccessTokenHash::new(access_token);
+        let moved = get_cache_store()
+            .rename_nx(
+                AccessTokenHash::legacy(access_token).get_key(),
+                key.get_key(),
+            )
+            .await
+            .map_err(unavailable)?;
+        if moved {
+            Self::get(key).await
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Forgets the user of an access token under both the current and the legacy key.
+    pub async fn delete_for_token(access_token: &str) -> ApiResult<()> {
+        Self::delete(AccessTokenHash::new(access_token)).await?;
+        Self::delete(AccessTokenHash::legacy(access_token)).await?;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct UserId(pub(crate) u64);
 impl CacheKey for UserId {
@@ -59,18 +116,54 @@ impl CacheKey for UserId {
     }
 }
 
+#[derive(Debug)]
+pub struct OauthState(pub String);
+impl CacheKey for OauthState {
+    const KEY: &'static str = "oauth_states";
+
+    fn get_key(&self) -> String {
+        format!("{}:{}", Self::KEY, self.0)
+    }
+}
+
+/// States of login attempts that haven't come back from Discord yet.
+pub struct OauthStateCache;
+impl Cache<OauthState, bool> for OauthStateCache {
+    fn get_expire(_key: &OauthState) -> Option<usize> {
+        Some(get_config().cache.oauth_state_ttl)
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionId(pub String);
+impl CacheKey for SessionId {
+    const KEY: &'static str = "sessions";
+
+    fn get_key(&self) -> String {
+        format!("{}:{}", Self::KEY, self.0)
+    }
+}
+
+pub struct SessionCache;
+impl Cache<SessionId, StoredSession> for SessionCache {
+    fn get_expire(_key: &SessionId) -> Option<usize> {
+        Some(get_config().cache.session_ttl)
+    }
+}
+
 pub struct GuildsCache;
+#[async_trait]
 impl Cache<UserId, Vec<Guild>> for GuildsCache {
-    fn get(key: UserId) -> Option<Vec<Guild>> {
-        Self::get_vec::<Guild>(key)
+    async fn get(key: UserId) -> ApiResult<Option<Vec<Guild>>> {
+        Self::get_vec::<Guild>(key).await
     }
 
-    fn set(key: UserId, value: &Vec<Guild>) -> bool {
-        Self::set_vec::<Guild>(key, value)
+    async fn set(key: UserId, value: &Vec<Guild>) -> bool {
+        Self::set_vec::<Guild>(key, value).await
     }
 
     fn get_expire(_key: &UserId) -> Option<usize> {
-        Some(1800)
+        Some(get_config().cache.guilds_ttl)
     }
 
     fn get_additional_namespace() -> Option<String> {
@@ -88,25 +181,54 @@ impl CacheKey for GuildId {
     }
 }
 
+/// Guild data cached from Discord that goes stale when the guild is edited.
+#[derive(Display, EnumString, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum GuildResource {
+    Channels,
+    Roles,
+    Members,
+}
+
+/// Drops the cached resources of a guild, the next read fetches them from Discord again.
+pub async fn invalidate_guild(guild_id: u64, resources: &[GuildResource]) {
+    for resource in resources.iter() {
+        let deleted = match resource {
+            GuildResource::Channels => ChannelsCache::delete(GuildId(guild_id)).await,
+            GuildResource::Roles => RolesCache::delete(GuildId(guild_id)).await,
+            GuildResource::Members => MembersCache::delete(GuildId(guild_id)).await,
+        };
+        if let Err(err) = deleted {
+            eprintln!(
+                "Failed to invalidate the {} of guild {}: {}",
+                resource, guild_id, err
+            );
+        }
+    }
+}
+
 redis_struct! {
     Channel;
     Role;
     Member;
     Guild;
+    StoredSession;
 }
 
 pub struct ChannelsCache;
+#[async_trait]
 impl Cache<GuildId, Vec<Channel>> for ChannelsCache {
-    fn get(key: GuildId) -> Option<Vec<Channel>> {
-        Self::get_vec::<Channel>(key)
+    async fn get(key: GuildId) -> ApiResult<Option<Vec<Channel>>> {
+        Self::get_vec::<Channel>(key).await
     }
 
-    fn set(key: GuildId, value: &Vec<Channel>) -> bool {
-        Self::set_vec::<Channel>(key, value)
+    async fn set(key: GuildId, value: &Vec<Channel>) -> bool {
+        Self::set_vec::<Channel>(key, value).await
     }
 
     fn get_expire(_key: &GuildId) -> Option<usize> {
-        Some(1800)
+        Some(get_config().cache.channels_ttl)
     }
 
     fn get_additional_namespace() -> Option<String> {
@@ -115,17 +237,18 @@ impl Cache<GuildId, Vec<Channel>> for ChannelsCache {
 }
 
 pub struct RolesCache;
+#[async_trait]
 impl Cache<GuildId, Vec<Role>> for RolesCache {
-    fn get(key: GuildId) -> Option<Vec<Role>> {
-        Self::get_vec::<Role>(key)
+    async fn get(key: GuildId) -> ApiResult<Option<Vec<Role>>> {
+        Self::get_vec::<Role>(key).await
     }
 
-    fn set(key: GuildId, value: &Vec<Role>) -> bool {
-        Self::set_vec::<Role>(key, value)
+    async fn set(key: GuildId, value: &Vec<Role>) -> bool {
+        Self::set_vec::<Role>(key, value).await
     }
 
     fn get_expire(_key: &GuildId) -> Option<usize> {
-        Some(1800)
+        Some(get_config().cache.roles_ttl)
     }
 
     fn get_additional_namespace() -> Option<String> {
@@ -134,17 +257,18 @@ impl Cache<GuildId, Vec<Role>> for RolesCache {
 }
 
 pub struct MembersCache;
+#[async_trait]
 impl Cache<GuildId, Vec<Member>> for MembersCache {
-    fn get(key: GuildId) -> Option<Vec<Member>> {
-        Self::get_vec::<Member>(key)
+    async fn get(key: GuildId) -> ApiResult<Option<Vec<Member>>> {
+        Self::get_vec::<Member>(key).await
     }
 
-    fn set(key: GuildId, value: &Vec<Member>) -> bool {
-        Self::set_vec::<Member>(key, value)
+    async fn set(key: GuildId, value: &Vec<Member>) -> bool {
+        Self::set_vec::<Member>(key, value).await
     }
 
     fn get_expire(_key: &GuildId) -> Option<usize> {
-        Some(1800)
+        Some(get_config().cache.members_ttl)
     }
 
     fn get_additional_namespace() -> Option<String> {
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::OwnedMutexGuard;
+
+static FLIGHTS: Lazy<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>> = Lazy::new(Default::default);
+
+fn flights() -> std::sync::MutexGuard<'static, HashMap<String, Arc<AsyncMutex<()>>>> {
+    FLIGHTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The right to fetch a key, held by one caller per process at a time so concurrent misses turn
+/// into a single Discord call. Released when dropped.
+pub struct Flight {
+    key: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Flight {
+    /// Waits for the caller fetching `key` to finish, if there is one.
+    pub async fn lock(key: &str) -> Flight {
+        let lock = flights().entry(key.to_string()).or_default().clone();
+        Flight {
+            key: key.to_string(),
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+
+    /// `None` when `key` is already being fetched.
+    pub fn try_lock(key: &str) -> Option<Flight> {
+        let lock = flights().entry(key.to_string()).or_default().clone();
+        let guard = lock.try_lock_owned().ok();
+        let flight = Flight {
+            key: key.to_string(),
+            guard,
+        };
+        flight.guard.is_some().then_some(flight)
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut flights = flights();
+        // Waiters clone the lock out of the map while holding it, so nobody can be about to.
+        if flights
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            flights.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use actix_web::rt::time::delay_for;
+
+    use super::*;
+
+    fn is_tracked(key: &str) -> bool {
+        flights().contains_key(key)
+    }
+
+    #[test]
+    fn try_lock_fails_while_held() {
+        let held = Flight::try_lock("test:held").unwrap();
+        assert!(Flight::try_lock("test:held").is_none());
+
+        drop(held);
+        assert!(Flight::try_lock("test:held").is_some());
+    }
+
+    #[test]
+    fn last_holder_removes_the_entry() {
+        let key = "test:removed";
+        let waited = Rc::new(Cell::new(false));
+        let done = waited.clone();
+        actix_web::rt::System::new("test").block_on(async move {
+            let held = Flight::lock(key).await;
+            actix_web::rt::spawn(async move {
+                let _flight = Flight::lock(key).await;
+                done.set(true);
+            });
+            delay_for(Duration::from_millis(20)).await;
+
+            drop(held);
+            assert!(is_tracked(key));
+            delay_for(Duration::from_millis(20)).await;
+        });
+
+        assert!(waited.get());
+        assert!(!is_tracked(key));
+    }
+}
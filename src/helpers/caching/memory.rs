@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+enum MemoryValue {
+    Bytes(Vec<u8>),
+    List(Vec<Vec<u8>>),
+}
+
+struct Entry {
+    value: MemoryValue,
+    expires_at: Option<Instant>,
+    /// Position in `MemoryState::usage`, bumped whenever the entry is used.
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    entries: HashMap<String, Entry>,
+    /// Keys ordered from least to most recently used.
+    usage: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl MemoryState {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// The live entry under `key`, marked as used. Expired entries are dropped on the way.
+    fn touch(&mut self, key: &str) -> Option<&mut Entry> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.expires_at.is_some_and(|at| at <= Instant::now()),
+            None => return None,
+        };
+        if expired {
+            self.remove(key);
+            return None;
+        }
+
+        let tick = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        self.usage.remove(&entry.last_used);
+        self.usage.insert(tick, key.to_string());
+        entry.last_used = tick;
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: String, value: MemoryValue, expire: Option<usize>, capacity: usize) {
+        self.remove(&key);
+        while self.entries.len() >= capacity {
+            match self.usage.keys().next().copied() {
+                Some(oldest) => {
+                    if let Some(evicted) = self.usage.remove(&oldest) {
+                        self.entries.remove(&evicted);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        let tick = self.tick();
+        self.usage.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                expires_at: expire.map(|secs| Instant::now() + Duration::from_secs(secs as u64)),
+                last_used: tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.usage.remove(&entry.last_used);
+        Some(entry)
+    }
+}
+
+/// An in-process cache that evicts the least recently used entries beyond its capacity. Values
+/// are lost on restart and not shared between instances, so it suits single instance setups.
+#[derive(Clone)]
+pub struct MemoryCache {
+    state: Arc<Mutex<MemoryState>>,
+    capacity: usize,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MemoryState::default())),
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        // A panic while holding the lock can't leave the maps half updated in a harmful way.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        match self.state().touch(key) {
+            Some(Entry {
+                value: MemoryValue::Bytes(bytes),
+                ..
+            }) => Some(bytes.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set(&self, key: String, value: Vec<u8>, expire: Option<usize>) {
+        self.state()
+            .insert(key, MemoryValue::Bytes(value), expire, self.capacity);
+    }
+
+    pub fn delete(&self, key: &str) -> bool {
+        let mut state = self.state();
+        // An expired entry counts as deleted already, like in Redis.
+        state.touch(key).is_some() && state.remove(key).is_some()
+    }
+
+    pub fn get_list(&self, key: &str) -> Option<Vec<Vec<u8>>> {
+        match self.state().touch(key) {
+            Some(Entry {
+                value: MemoryValue::List(items),
+                ..
+            }) => Some(items.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set_list(&self, key: String, items: Vec<Vec<u8>>, expire: Option<usize>) {
+        self.state()
+            .insert(key, MemoryValue::List(items), expire, self.capacity);
+    }
+
+    /// Moves `from` to `to` unless `to` exists, keeping the expiry.
+    pub fn rename_nx(&self, from: &str, to: &str) -> bool {
+        let mut state = self.state();
+        if state.touch(to).is_some() || state.touch(from).is_none() {
+            return false;
+        }
+
+        match state.remove(from) {
+            Some(entry) => {
+                let tick = state.tick();
+                state.usage.insert(tick, to.to_string());
+                state.entries.insert(
+                    to.to_string(),
+                    Entry {
+                        last_used: tick,
+                        ..entry
+                    },
+                );
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(value: &str) -> Vec<u8> {
+        value.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let cache = MemoryCache::new(2);
+        cache.set("a".into(), bytes("1"), None);
+        cache.set("b".into(), bytes("2"), None);
+        cache.get("a");
+        cache.set("c".into(), bytes("3"), None);
+
+        assert_eq!(cache.get("a"), Some(bytes("1")));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(bytes("3")));
+    }
+
+    #[test]
+    fn replacing_an_entry_does_not_evict() {
+        let cache = MemoryCache::new(2);
+        cache.set("a".into(), bytes("1"), None);
+        cache.set("b".into(), bytes("2"), None);
+        cache.set("a".into(), bytes("3"), None);
+
+        assert_eq!(cache.get("a"), Some(bytes("3")));
+        assert_eq!(cache.get("b"), Some(bytes("2")));
+    }
+
+    #[test]
+    fn expired_entries_are_gone() {
+        let cache = MemoryCache::new(4);
+        cache.set("expired".into(), bytes("1"), Some(0));
+        cache.set("live".into(), bytes("2"), Some(60));
+        cache.set_list("expired_list".into(), vec![bytes("1")], Some(0));
+
+        assert_eq!(cache.get("expired"), None);
+        assert!(!cache.delete("expired"));
+        assert_eq!(cache.get_list("expired_list"), None);
+        assert_eq!(cache.get("live"), Some(bytes("2")));
+        assert!(!cache.state().entries.contains_key("expired"));
+    }
+
+    #[test]
+    fn rename_nx_only_moves_to_a_free_key() {
+        let cache = MemoryCache::new(4);
+        cache.set("from".into(), bytes("1"), Some(60));
+        cache.set("taken".into(), bytes("2"), None);
+
+        assert!(!cache.rename_nx("from", "taken"));
+        assert!(!cache.rename_nx("missing", "to"));
+        assert!(cache.rename_nx("from", "to"));
+
+        assert_eq!(cache.get("from"), None);
+        assert_eq!(cache.get("to"), Some(bytes("1")));
+        assert_eq!(cache.get("taken"), Some(bytes("2")));
+        assert!(cache.state().entries["to"].expires_at.is_some());
+    }
+
+    #[test]
+    fn lists_keep_their_order_and_can_be_empty() {
+        let cache = MemoryCache::new(4);
+        cache.set_list("list".into(), vec![bytes("b"), bytes("a")], None);
+        cache.set_list("empty".into(), Vec::new(), None);
+
+        assert_eq!(cache.get_list("list"), Some(vec![bytes("b"), bytes("a")]));
+        assert_eq!(cache.get_list("empty"), Some(Vec::new()));
+        assert_eq!(cache.get_list("missing"), None);
+        assert!(cache.delete("empty"));
+        assert_eq!(cache.get_list("empty"), None);
+    }
+}
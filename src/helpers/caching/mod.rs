@@ -1,3 +1,5 @@
 pub mod base;
 pub mod discord;
+pub mod flight;
 pub mod macros;
+pub mod memory;
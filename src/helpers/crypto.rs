@@ -0,0 +1,122 @@
+use aes_gcm::aead::Aead;
+use aes_gcm::aead::NewAead;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::Key;
+use aes_gcm::Nonce;
+use hmac::Hmac;
+use hmac::Mac;
+use hmac::NewMac;
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::config::get_config;
+
+const NONCE_SIZE: usize = 12;
+
+/// Keyed with its own key derived from `SESSION_SECRET`, so the secret can be any string.
+static CIPHER: Lazy<Aes256Gcm> = Lazy::new(|| {
+    let key = derive_key(get_secret(), "session-encryption");
+    Aes256Gcm::new(Key::from_slice(&key))
+});
+
+/// Keys `keyed_hash`, kept apart from the encryption key so neither use weakens the other.
+static HASH_KEY: Lazy<Vec<u8>> = Lazy::new(|| derive_key(get_secret(), "token-hash"));
+
+fn get_secret() -> &'static str {
+    &get_config().session.secret
+}
+
+fn hmac(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(value);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// A 256 bit key for one use of the secret, `label` names the use.
+fn derive_key(secret: &str, label: &str) -> Vec<u8> {
+    hmac(secret.as_bytes(), label.as_bytes())
+}
+
+fn random_bytes(size: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; size];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// An unguessable URL safe token, e.g. for session ids.
+pub fn random_token() -> String {
+    base64::encode_config(random_bytes(32), base64::URL_SAFE_NO_PAD)
+}
+
+/// Encrypts and authenticates a value, the result holds the nonce followed by the ciphertext.
+pub fn encrypt(plaintext: &str) -> Result<String, String> {
+    let nonce = random_bytes(NONCE_SIZE);
+    let mut ciphertext = CIPHER
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| String::from("Failed to encrypt."))?;
+
+    let mut output = nonce;
+    output.append(&mut ciphertext);
+    Ok(base64::encode(output))
+}
+
+pub fn decrypt(encrypted: &str) -> Result<String, String> {
+    let bytes = base64::decode(encrypted).map_err(|e| format!("{}", e))?;
+    if bytes.len() < NONCE_SIZE {
+        return Err("Encrypted value is too short.".into());
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+    let plaintext = CIPHER
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| String::from("Failed to decrypt."))?;
+    String::from_utf8(plaintext).map_err(|e| format!("{}", e))
+}
+
+fn hash_with_key(key: &[u8], value: &str) -> String {
+    hmac(key, value.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// HMAC-SHA256 of the value as lowercase hex, keyed with a key derived from `SESSION_SECRET`.
+/// Stable across restarts and unguessable without the secret, so it is safe to use in cache keys.
+pub fn keyed_hash(value: &str) -> String {
+    hash_with_key(&HASH_KEY, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::install_test_config;
+
+    use super::*;
+
+    #[test]
+    fn keyed_hash_is_stable() {
+        install_test_config();
+        let expected = hash_with_key(&derive_key("test secret", "token-hash"), "token");
+
+        assert_eq!(keyed_hash("token"), expected);
+        assert_eq!(keyed_hash("token"), keyed_hash("token"));
+        assert_ne!(keyed_hash("token"), keyed_hash("other token"));
+    }
+
+    #[test]
+    fn keyed_hash_depends_on_the_secret() {
+        let hash = |secret: &str| hash_with_key(&derive_key(secret, "token-hash"), "token");
+
+        assert_ne!(hash("one secret"), hash("another secret"));
+    }
+
+    #[test]
+    fn keys_differ_per_use() {
+        let encryption = derive_key("secret", "session-encryption");
+        let hashing = derive_key("secret", "token-hash");
+
+        assert_ne!(encryption, hashing);
+        assert_ne!(encryption, b"secret".to_vec());
+        assert_eq!(encryption.len(), 32);
+    }
+}
@@ -0,0 +1,79 @@
+use super::base::Backend;
+use super::base::Database;
+use super::base::DatabaseResult;
+use super::base::Executor;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub mysql: &'static str,
+    pub sqlite: &'static str,
+}
+
+impl Migration {
+    fn get_sql(&self, backend: Backend) -> &'static str {
+        match backend {
+            Backend::MySql => self.mysql,
+            Backend::Sqlite => self.sqlite,
+        }
+    }
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            mysql: include_str!(concat!("../../../migrations/mysql/", $name, ".sql")),
+            sqlite: include_str!(concat!("../../../migrations/sqlite/", $name, ".sql")),
+        }
+    };
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_create_polls"),
+    migration!(2, "0002_add_poll_status"),
+    migration!(3, "0003_create_poll_settings"),
+    migration!(4, "0004_add_poll_changes"),
+    migration!(5, "0005_add_poll_change_claimed_at"),
+];
+
+fn get_applied_versions(connection: &mut dyn Executor) -> DatabaseResult<Vec<i64>> {
+    connection.batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT NOT NULL PRIMARY KEY,
+            name    VARCHAR(255) NOT NULL
+        )",
+    )?;
+
+    connection
+        .query("SELECT version FROM schema_migrations", &[])?
+        .iter()
+        .map(|row| row.get_i64(0))
+        .collect()
+}
+
+/// Applies every migration that hasn't been recorded in `schema_migrations` yet, in order.
+pub fn run_migrations(database: &Database) -> DatabaseResult<()> {
+    let backend = database.backend();
+    let applied = database.with_connection(get_applied_versions)?;
+
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        database
+            .with_transaction(|connection| {
+                for statement in migration.get_sql(backend).split(';') {
+                    if !statement.trim().is_empty() {
+                        connection.execute(statement, &[])?;
+                    }
+                }
+                connection.execute(
+                    "INSERT INTO schema_migrations (version, name) VALUES (?, ?)",
+                    &[migration.version.into(), migration.name.into()],
+                )?;
+                Ok(())
+            })
+            .map_err(|e| format!("Migration {} failed: {}", migration.name, e))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use actix_web::ResponseError;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::discord::base_api::TokenKind;
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Serialize, Debug)]
+pub struct FieldError {
+    /// Path of the offending input, e.g. `channel_id` or `options[2].value`.
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &str, message: &str) {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    /// Missing, invalid or expired credentials.
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    /// The resource is in a state that doesn't allow the request.
+    Conflict(String),
+    /// The input is well-formed but some fields are invalid, each is listed in the details.
+    Validation(ValidationErrors),
+    /// Still rate limited after retrying, the frontend may try again after the duration.
+    RateLimited(Duration),
+    /// Discord failed or answered with something we couldn't use.
+    BadGateway {
+        message: String,
+        details: Option<Value>,
+    },
+    /// A dependency such as Discord or Redis could not be reached.
+    Unavailable(String),
+    /// Logged, clients only get a generic message since it may contain database or driver
+    /// details.
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: String,
+    details: Option<&'a Value>,
+}
+
+impl ApiError {
+    /// Maps an error status Discord answered with, `body` is the JSON error Discord sent if any.
+    /// A rejected bot token is our problem rather than the user's, so it never reaches the
+    /// frontend as `unauthorized` or `forbidden`.
+    pub fn from_discord(status: StatusCode, body: Option<Value>, token: TokenKind) -> Self {
+        match (status, token) {
+            (StatusCode::UNAUTHORIZED, TokenKind::Bot) => {
+                ApiError::Unavailable("Discord rejected the bot token.".into())
+            }
+            (StatusCode::FORBIDDEN, TokenKind::Bot) => ApiError::BadGateway {
+                message: "Discord denied the bot access.".into(),
+                details: Some(json!({ "status": status.as_u16(), "response": body })),
+            },
+            (StatusCode::UNAUTHORIZED, _) => {
+                ApiError::Unauthorized("Discord rejected the access token.".into())
+            }
+            (StatusCode::FORBIDDEN, _) => ApiError::Forbidden("Discord denied access.".into()),
+            (StatusCode::NOT_FOUND, _) => ApiError::NotFound("Discord couldn't find it.".into()),
+            (StatusCode::TOO_MANY_REQUESTS, _) => ApiError::RateLimited(Duration::from_secs(1)),
+            _ => ApiError::BadGateway {
+                message: format!("Discord responded with {}.", status),
+                details: Some(json!({ "status": status.as_u16(), "response": body })),
+            },
+        }
+    }
+
+    /// Maps a failure to send a request or read its response.
+    pub fn from_reqwest(err: reqwest::Error) -> Self {
+        if err.is_connect() || err.is_timeout() {
+            ApiError::Unavailable(format!("Discord could not be reached: {}", err))
+        } else {
+            ApiError::BadGateway {
+                message: format!("{}", err),
+                details: None,
+            }
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::BadGateway { .. } => "bad_gateway",
+            ApiError::Unavailable(_) => "service_unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Internal(message)
+            | ApiError::BadGateway { message, .. } => write!(f, "{}", message),
+            ApiError::Validation(_) => write!(f, "Some fields are invalid."),
+            ApiError::RateLimited(retry_after) => write!(
+                f,
+                "Rate limited by Discord, retry after {:.1}s.",
+                retry_after.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::BadGateway { .. } => StatusCode::BAD_GATEWAY,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let own_details;
+        let details = match self {
+            ApiError::BadGateway { details, .. } => details.as_ref(),
+            ApiError::Validation(errors) => {
+                own_details = json!(errors.errors);
+                Some(&own_details)
+            }
+            ApiError::RateLimited(retry_after) => {
+                own_details = json!({ "retry_after": retry_after.as_secs_f64() });
+                Some(&own_details)
+            }
+            _ => None,
+        };
+
+        let message = match self {
+            ApiError::Internal(message) => {
+                eprintln!("Internal error: {}", message);
+                "Something went wrong on our side, try again later.".to_string()
+            }
+            _ => self.to_string(),
+        };
+
+        let mut response = HttpResponseBuilder::new(self.status_code());
+        if let ApiError::RateLimited(retry_after) = self {
+            response.header(RETRY_AFTER, retry_after.as_secs_f64().ceil().to_string());
+        }
+        response.json(ErrorBody {
+            code: self.code(),
+            message,
+            details,
+        })
+    }
+}
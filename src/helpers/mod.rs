@@ -1,3 +1,6 @@
 pub mod caching;
+pub mod crypto;
+pub mod database;
+pub mod errors;
 pub mod repositories;
 pub mod validator;
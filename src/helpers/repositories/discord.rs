@@ -1,20 +1,26 @@
-use std::env;
+use std::collections::HashSet;
+use std::fmt;
 
+use crate::config::get_config;
 use crate::discord::calls::ChannelKind;
 use crate::discord::calls::GetChannels;
 use crate::discord::calls::GetGuilds;
 use crate::discord::calls::GetMembers;
 use crate::discord::calls::GetRoles;
+use crate::discord::calls::GuildResponse;
 use crate::discord::discord_base::AccessToken;
 use crate::discord::discord_base::DiscordCall;
 
 use crate::discord::base_api::Callable;
 use crate::discord::models::Guild;
+use crate::helpers::caching::base::get_cache_store;
 use crate::helpers::caching::base::Cache;
 
 use crate::helpers::caching::base::CacheKey;
 use crate::helpers::caching::discord::ChannelsCache;
 use crate::helpers::caching::discord::GuildId;
+use crate::helpers::caching::discord::GuildResource;
+use crate::helpers::caching::flight::Flight;
 
 use crate::discord::models::Channel;
 use crate::discord::models::Member;
@@ -23,32 +29,93 @@ use crate::helpers::caching::discord::GuildsCache;
 use crate::helpers::caching::discord::MembersCache;
 use crate::helpers::caching::discord::RolesCache;
 use crate::helpers::caching::discord::UserId;
+use crate::helpers::errors::ApiError;
+use crate::helpers::errors::ApiResult;
 
 use async_trait::async_trait;
 use serde::de::DeserializeOwned;
 
+fn invalid_from_discord(what: &str, err: impl fmt::Display) -> ApiError {
+    ApiError::BadGateway {
+        message: format!("Invalid {} from Discord: {}", what, err),
+        details: None,
+    }
+}
+
+fn get_fresh_key(key: &str) -> String {
+    format!("{}:fresh", key)
+}
+
+/// Whether `key` was cached less than `stale_after` ago. Errors count as fresh so a struggling
+/// cache doesn't also cause a refresh per read.
+async fn is_fresh(key: &str) -> bool {
+    !matches!(get_cache_store().get(get_fresh_key(key)).await, Ok(None))
+}
+
+async fn mark_fresh(key: &str) {
+    let stale_after = get_config().cache.stale_after;
+    let _ = get_cache_store()
+        .set(get_fresh_key(key), b"1".to_vec(), Some(stale_after))
+        .await;
+}
+
 #[async_trait]
-pub trait Repository<D: DeserializeOwned + Send + Sized, F: Send + Sync> {
-    async fn get(f: &F) -> Result<Vec<D>, String> {
-        match Self::get_cached(f).await {
-            Ok(channels) => Ok(channels),
-            Err(_) => {
-                let uncached = Self::get_uncached(f).await?;
-                let _ = Self::cache(f, &uncached);
-                Ok(uncached)
+pub trait Repository<D, F>
+where
+    D: DeserializeOwned + Send + Sized,
+    F: Clone + Send + Sync + 'static,
+    Self: Send + 'static,
+{
+    /// Serves the cache, refreshing it in the background once it's stale. Concurrent misses for
+    /// the same key wait for a single Discord call instead of each making their own.
+    async fn get(f: &F) -> ApiResult<Vec<D>> {
+        let key = Self::get_key(f);
+        if let Some(values) = Self::get_cached(f).await {
+            if !is_fresh(&key).await {
+                Self::revalidate(f.clone(), &key);
             }
+            return Ok(values);
+        }
+
+        let _flight = Flight::lock(&key).await;
+        match Self::get_cached(f).await {
+            Some(values) => Ok(values),
+            None => Self::refresh(f).await,
+        }
+    }
+
+    /// Fetches from Discord and replaces what was cached, for when the cache is known to be stale.
+    async fn refresh(f: &F) -> ApiResult<Vec<D>> {
+        let uncached = Self::get_uncached(f).await?;
+        if Self::cache(f, &uncached).await {
+            mark_fresh(&Self::get_key(f)).await;
+        }
+        Ok(uncached)
+    }
+
+    /// Refreshes on the current worker without waiting, unless a refresh of `key` is running.
+    fn revalidate(f: F, key: &str) {
+        if let Some(flight) = Flight::try_lock(key) {
+            actix_web::rt::spawn(async move {
+                let _flight = flight;
+                let _ = Self::refresh(&f).await;
+            });
         }
     }
 
-    fn cache(f: &F, values: &Vec<D>) -> bool;
-    async fn get_cached(f: &F) -> Result<Vec<D>, String>;
-    async fn get_uncached(f: &F) -> Result<Vec<D>, String>;
+    /// The key of the cache entry, used to coalesce refreshes and track staleness.
+    fn get_key(f: &F) -> String;
+    async fn cache(f: &F, values: &[D]) -> bool;
+    /// `None` on a miss, and also when the cache is unreachable so the values come from Discord.
+    async fn get_cached(f: &F) -> Option<Vec<D>>;
+    async fn get_uncached(f: &F) -> ApiResult<Vec<D>>;
 }
 
 pub trait RepositoryOptions<D: CacheKey> {
     fn get_cache_key(&self) -> D;
 }
 
+#[derive(Clone)]
 pub struct ChannelRepositoryOptions(pub u64, pub ChannelKind);
 impl RepositoryOptions<GuildId> for ChannelRepositoryOptions {
     fn get_cache_key(&self) -> GuildId {
@@ -59,21 +126,26 @@ impl RepositoryOptions<GuildId> for ChannelRepositoryOptions {
 pub struct ChannelRepository;
 #[async_trait]
 impl Repository<Channel, ChannelRepositoryOptions> for ChannelRepository {
-    fn cache(options: &ChannelRepositoryOptions, values: &Vec<Channel>) -> bool {
-        ChannelsCache::set(options.get_cache_key(), &values)
+    fn get_key(options: &ChannelRepositoryOptions) -> String {
+        ChannelsCache::get_full_key(&options.get_cache_key())
     }
 
-    async fn get_cached(options: &ChannelRepositoryOptions) -> Result<Vec<Channel>, String> {
-        let channels =
-            ChannelsCache::get(options.get_cache_key()).ok_or(String::from("Not found."))?;
-        Ok(channels
-            .into_iter()
-            .filter(|c| &c.kind == &options.1)
-            .collect::<Vec<Channel>>())
+    async fn cache(options: &ChannelRepositoryOptions, values: &[Channel]) -> bool {
+        ChannelsCache::set_vec(options.get_cache_key(), values).await
+    }
+
+    async fn get_cached(options: &ChannelRepositoryOptions) -> Option<Vec<Channel>> {
+        let channels = ChannelsCache::get(options.get_cache_key()).await.ok()??;
+        Some(
+            channels
+                .into_iter()
+                .filter(|c| c.kind == options.1)
+                .collect::<Vec<Channel>>(),
+        )
     }
 
-    async fn get_uncached(f: &ChannelRepositoryOptions) -> Result<Vec<Channel>, String> {
-        let call = DiscordCall::new(AccessToken::Bot(env::var("DISCORD_CLIENT_TOKEN").unwrap()));
+    async fn get_uncached(f: &ChannelRepositoryOptions) -> ApiResult<Vec<Channel>> {
+        let call = DiscordCall::bot();
         let result = call.call(GetChannels { guild_id: f.0 }).await?;
 
         let channels: Vec<Channel> = result
@@ -84,6 +156,7 @@ impl Repository<Channel, ChannelRepositoryOptions> for ChannelRepository {
                 id: c.id,
                 name: c.name.clone(),
                 kind: c.kind.clone(),
+                permission_overwrites: c.permission_overwrites,
             })
             .collect();
 
@@ -91,6 +164,7 @@ impl Repository<Channel, ChannelRepositoryOptions> for ChannelRepository {
     }
 }
 
+#[derive(Clone)]
 pub struct SharedRepositoryOptions(pub u64);
 impl RepositoryOptions<GuildId> for SharedRepositoryOptions {
     fn get_cache_key(&self) -> GuildId {
@@ -101,16 +175,23 @@ impl RepositoryOptions<GuildId> for SharedRepositoryOptions {
 pub struct RoleRepository;
 #[async_trait]
 impl Repository<Role, SharedRepositoryOptions> for RoleRepository {
-    fn cache(options: &SharedRepositoryOptions, values: &Vec<Role>) -> bool {
-        RolesCache::set(options.get_cache_key(), &values)
+    fn get_key(options: &SharedRepositoryOptions) -> String {
+        RolesCache::get_full_key(&options.get_cache_key())
+    }
+
+    async fn cache(options: &SharedRepositoryOptions, values: &[Role]) -> bool {
+        RolesCache::set_vec(options.get_cache_key(), values).await
     }
 
-    async fn get_cached(options: &SharedRepositoryOptions) -> Result<Vec<Role>, String> {
-        RolesCache::get(options.get_cache_key()).ok_or(String::from("Not found."))
+    async fn get_cached(options: &SharedRepositoryOptions) -> Option<Vec<Role>> {
+        RolesCache::get(options.get_cache_key())
+            .await
+            .ok()
+            .flatten()
     }
 
-    async fn get_uncached(f: &SharedRepositoryOptions) -> Result<Vec<Role>, String> {
-        let call = DiscordCall::new(AccessToken::Bot(env::var("DISCORD_CLIENT_TOKEN").unwrap()));
+    async fn get_uncached(f: &SharedRepositoryOptions) -> ApiResult<Vec<Role>> {
+        let call = DiscordCall::bot();
         let result = call.call(GetRoles { guild_id: f.0 }).await?;
 
         let roles: Vec<Role> = result
@@ -119,6 +200,8 @@ impl Repository<Role, SharedRepositoryOptions> for RoleRepository {
             .map(|c| Role {
                 id: c.id,
                 name: c.name.clone(),
+                permissions: c.permissions,
+                position: c.position,
             })
             .collect();
 
@@ -130,32 +213,69 @@ pub struct MemberRepository;
 
 #[async_trait]
 impl Repository<Member, SharedRepositoryOptions> for MemberRepository {
-    fn cache(options: &SharedRepositoryOptions, values: &Vec<Member>) -> bool {
-        MembersCache::set(options.get_cache_key(), &values)
+    fn get_key(options: &SharedRepositoryOptions) -> String {
+        MembersCache::get_full_key(&options.get_cache_key())
     }
 
-    async fn get_cached(options: &SharedRepositoryOptions) -> Result<Vec<Member>, String> {
-        MembersCache::get(options.get_cache_key()).ok_or(String::from("Not found."))
+    async fn cache(options: &SharedRepositoryOptions, values: &[Member]) -> bool {
+        MembersCache::set_vec(options.get_cache_key(), values).await
     }
 
-    async fn get_uncached(f: &SharedRepositoryOptions) -> Result<Vec<Member>, String> {
-        let call = DiscordCall::new(AccessToken::Bot(env::var("DISCORD_CLIENT_TOKEN").unwrap()));
-        let result = call.call(GetMembers(f.0)).await?;
+    async fn get_cached(options: &SharedRepositoryOptions) -> Option<Vec<Member>> {
+        MembersCache::get(options.get_cache_key())
+            .await
+            .ok()
+            .flatten()
+    }
 
-        let members: Vec<Member> = result
-            .members
-            .into_iter()
-            .map(|c| Member {
-                id: c.user.id,
-                username: c.user.username.clone(),
-                discriminator: c.user.discriminator.parse::<u16>().unwrap(),
-            })
-            .collect();
+    async fn get_uncached(f: &SharedRepositoryOptions) -> ApiResult<Vec<Member>> {
+        let call = DiscordCall::bot();
+        let max_members = get_config().discord.max_members;
+
+        let mut members: Vec<Member> = Vec::new();
+        let mut after: Option<u64> = None;
+        loop {
+            let result = call
+                .call(GetMembers {
+                    guild_id: f.0,
+                    limit: GetMembers::MAX_LIMIT,
+                    after,
+                })
+                .await?;
+            let page_size = result.members.len();
+
+            for member in result.members.into_iter() {
+                let id = member
+                    .user
+                    .id
+                    .parse::<u64>()
+                    .map_err(|e| invalid_from_discord("member id", e))?;
+                let discriminator = member
+                    .user
+                    .discriminator
+                    .parse::<u16>()
+                    .map_err(|e| invalid_from_discord("member discriminator", e))?;
+                after = Some(after.map_or(id, |a| a.max(id)));
+                members.push(Member {
+                    id: member.user.id,
+                    username: member.user.username,
+                    discriminator,
+                    nick: member.nick,
+                    roles: member.roles,
+                });
+            }
+
+            if page_size < GetMembers::MAX_LIMIT as usize || members.len() >= max_members {
+                break;
+            }
+        }
+        members.truncate(max_members);
 
         Ok(members)
     }
 }
 
+#[derive(Clone)]
 pub struct GuildRepositoryOptions(pub u64, pub AccessToken);
 impl RepositoryOptions<UserId> for GuildRepositoryOptions {
     fn get_cache_key(&self) -> UserId {
@@ -163,37 +283,182 @@ impl RepositoryOptions<UserId> for GuildRepositoryOptions {
     }
 }
 
+/// Replaces the cached resource of a guild with what Discord has now.
+pub async fn refresh_guild(guild_id: u64, resource: GuildResource) -> ApiResult<()> {
+    match resource {
+        GuildResource::Channels => {
+            ChannelRepository::refresh(&ChannelRepositoryOptions(guild_id, ChannelKind::GuildText))
+                .await?;
+        }
+        GuildResource::Roles => {
+            RoleRepository::refresh(&SharedRepositoryOptions(guild_id)).await?;
+        }
+        GuildResource::Members => {
+            MemberRepository::refresh(&SharedRepositoryOptions(guild_id)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The user's guilds split by whether the bot is in them.
+pub struct GuildLists {
+    pub mutual: Vec<Guild>,
+    pub without_bot: Vec<Guild>,
+}
+
+fn to_guild(guild: GuildResponse) -> Guild {
+    Guild {
+        id: guild.id,
+        name: guild.name,
+        icon: guild.icon,
+        owner: guild.owner,
+        permissions: guild.permissions,
+    }
+}
+
+/// Every guild of the caller, Discord returns at most one page of 200 per request.
+async fn get_all_guilds(call: &DiscordCall) -> ApiResult<Vec<GuildResponse>> {
+    let mut guilds: Vec<GuildResponse> = Vec::new();
+    let mut after: Option<u64> = None;
+    loop {
+        let result = call
+            .call(GetGuilds {
+                limit: GetGuilds::MAX_LIMIT,
+                after,
+            })
+            .await?;
+        let page_size = result.guilds.len();
+
+        for guild in result.guilds.into_iter() {
+            let id = guild.id.parse::<u64>().map_err(|e| ApiError::BadGateway {
+                message: format!("Invalid guild id from Discord: {}", e),
+                details: None,
+            })?;
+            after = Some(after.map_or(id, |a| a.max(id)));
+            guilds.push(guild);
+        }
+
+        if page_size < GetGuilds::MAX_LIMIT as usize {
+            return Ok(guilds);
+        }
+    }
+}
+
+async fn get_guild_lists_uncached(options: &GuildRepositoryOptions) -> ApiResult<GuildLists> {
+    let bot_call = DiscordCall::bot();
+    let user_call = DiscordCall::new(options.1.clone());
+
+    let user_guilds = get_all_guilds(&user_call).await?;
+    let bot_guilds = get_all_guilds(&bot_call).await?;
+    let bot_guild_ids: HashSet<String> = bot_guilds.into_iter().map(|g| g.id).collect();
+
+    let (mutual, without_bot): (Vec<Guild>, Vec<Guild>) = user_guilds
+        .into_iter()
+        .map(to_guild)
+        .partition(|g| bot_guild_ids.contains(&g.id));
+
+    Ok(GuildLists {
+        mutual,
+        without_bot,
+    })
+}
+
+/// Both lists straight from Discord, the mutual guilds are cached for the validator on the way.
+pub async fn get_guild_lists(options: &GuildRepositoryOptions) -> ApiResult<GuildLists> {
+    let lists = get_guild_lists_uncached(options).await?;
+    let _ = MutualGuildRepository::cache(options, &lists.mutual).await;
+    Ok(lists)
+}
+
 pub struct MutualGuildRepository;
 #[async_trait]
 impl Repository<Guild, GuildRepositoryOptions> for MutualGuildRepository {
-    fn cache(options: &GuildRepositoryOptions, values: &Vec<Guild>) -> bool {
-        GuildsCache::set(options.get_cache_key(), &values)
+    fn get_key(options: &GuildRepositoryOptions) -> String {
+        GuildsCache::get_full_key(&options.get_cache_key())
     }
 
-    async fn get_cached(options: &GuildRepositoryOptions) -> Result<Vec<Guild>, String> {
-        GuildsCache::get(options.get_cache_key()).ok_or(String::from("Not found."))
+    async fn cache(options: &GuildRepositoryOptions, values: &[Guild]) -> bool {
+        GuildsCache::set_vec(options.get_cache_key(), values).await
     }
 
-    async fn get_uncached(options: &GuildRepositoryOptions) -> Result<Vec<Guild>, String> {
-        let bot_call =
-            DiscordCall::new(AccessToken::Bot(env::var("DISCORD_CLIENT_TOKEN").unwrap()));
-        let user_call = DiscordCall::new(options.1.clone());
+    async fn get_cached(options: &GuildRepositoryOptions) -> Option<Vec<Guild>> {
+        GuildsCache::get(options.get_cache_key())
+            .await
+            .ok()
+            .flatten()
+    }
 
-        let user_guilds = user_call.call(GetGuilds).await?;
-        let bot_guilds = bot_call.call(GetGuilds).await?;
+    async fn get_uncached(options: &GuildRepositoryOptions) -> ApiResult<Vec<Guild>> {
+        Ok(get_guild_lists_uncached(options).await?.mutual)
+    }
+}
 
-        let mut guilds: Vec<Guild> = Vec::new();
-        for guild in user_guilds.guilds.iter() {
-            for other_guild in bot_guilds.guilds.iter() {
-                if guild.id == other_guild.id {
-                    guilds.push(Guild {
-                        id: guild.id.clone(),
-                        name: guild.name.clone(),
-                    });
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use actix_web::rt::time::delay_for;
+
+    use crate::config::install_test_config;
+    use crate::helpers::caching::base::install_test_store;
+
+    use super::*;
+
+    static UNCACHED_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Caches the strings it "fetches" as raw bytes and counts the fetches.
+    struct CountingRepository;
+
+    #[async_trait]
+    impl Repository<String, u64> for CountingRepository {
+        fn get_key(f: &u64) -> String {
+            format!("test:counting:{}", f)
         }
 
-        Ok(guilds)
+        async fn cache(f: &u64, values: &[String]) -> bool {
+            let items = values.iter().map(|v| v.as_bytes().to_vec()).collect();
+            get_cache_store()
+                .set_list(Self::get_key(f), items, None)
+                .await
+                .is_ok()
+        }
+
+        async fn get_cached(f: &u64) -> Option<Vec<String>> {
+            let items = get_cache_store().get_list(Self::get_key(f)).await.ok()??;
+            items
+                .into_iter()
+                .map(|i| String::from_utf8(i).ok())
+                .collect()
+        }
+
+        async fn get_uncached(_: &u64) -> ApiResult<Vec<String>> {
+            UNCACHED_CALLS.fetch_add(1, Ordering::SeqCst);
+            delay_for(Duration::from_millis(50)).await;
+            Ok(vec!["fetched".into()])
+        }
+    }
+
+    #[test]
+    fn concurrent_misses_fetch_once() {
+        install_test_config();
+        install_test_store();
+        let spawned = Rc::new(RefCell::new(None));
+        let spawned_result = spawned.clone();
+        let inline = actix_web::rt::System::new("test").block_on(async move {
+            actix_web::rt::spawn(async move {
+                *spawned_result.borrow_mut() = Some(CountingRepository::get(&1).await);
+            });
+            let inline = CountingRepository::get(&1).await;
+            delay_for(Duration::from_millis(20)).await;
+            inline
+        });
+
+        assert_eq!(UNCACHED_CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(inline.unwrap(), ["fetched"]);
+        assert_eq!(spawned.borrow_mut().take().unwrap().unwrap(), ["fetched"]);
     }
 }
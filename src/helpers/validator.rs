@@ -1,51 +1,38 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hash;
-use std::hash::Hasher;
-
 use actix_web::HttpRequest;
 
 use crate::discord::base_api::Callable;
 use crate::discord::calls::GetMe;
 use crate::discord::discord_base::AccessToken;
 use crate::discord::discord_base::DiscordCall;
+use crate::discord::models::parse_user_id;
 use crate::discord::models::Guild;
 use crate::helpers::caching::base::Cache;
 use crate::helpers::caching::discord::AccessTokenHash;
 use crate::helpers::caching::discord::UserIdCache;
+use crate::helpers::errors::ApiError;
+use crate::helpers::errors::ApiResult;
+use crate::oauth::session::get_access_token;
 
+use super::repositories::discord::get_guild_lists as get_user_guild_lists;
+use super::repositories::discord::GuildLists;
 use super::repositories::discord::GuildRepositoryOptions;
 use super::repositories::discord::MutualGuildRepository;
 use super::repositories::discord::Repository;
 
-pub fn parse_access_token(req: &HttpRequest) -> Option<String> {
-    let value = req.headers().get("Authorization")?;
-    Some(value.to_str().ok()?.split(" ").last()?.into())
-}
-
-async fn get_user_id(access_token: &str) -> Result<u64, String> {
-    let mut hasher = DefaultHasher::new();
-    access_token.hash(&mut hasher);
-    let hash = hasher.finish().to_string();
-    let key = AccessTokenHash::new(&hash);
-
-    match UserIdCache::get(key) {
+async fn get_user_id(access_token: &str) -> ApiResult<u64> {
+    match UserIdCache::get_for_token(access_token).await? {
         Some(user_id) => Ok(user_id),
         None => {
             let call = DiscordCall::new(AccessToken::Bearer(access_token.into()));
-            let result = call.call(GetMe).await;
-            if let Ok(me) = result {
-                if let Ok(user_id) = me.id.parse::<u64>() {
-                    UserIdCache::set(AccessTokenHash::new(&hash), &user_id);
-                    return Ok(user_id);
-                }
-            }
-
-            Err("Couldn't find user id".into())
+            let me = call.call(GetMe).await?;
+            let user_id = parse_user_id(&me.id)?;
+            UserIdCache::set(AccessTokenHash::new(access_token), &user_id).await;
+            Ok(user_id)
         }
     }
 }
 
-pub async fn get_allowed_guilds(access_token: &str) -> Result<Vec<Guild>, String> {
+pub async fn get_allowed_guilds(access_token: &str) -> ApiResult<Vec<Guild>> {
     let user_id = get_user_id(access_token).await?;
 
     let options = GuildRepositoryOptions(user_id, AccessToken::Bearer(access_token.into()));
@@ -54,37 +41,82 @@ pub async fn get_allowed_guilds(access_token: &str) -> Result<Vec<Guild>, String
     Ok(guilds)
 }
 
-type ValidationResult = Result<ValidationInfo, String>;
+/// The user's guilds with and without the bot, always fetched fresh since the user may just have
+/// invited the bot.
+pub async fn get_guild_lists(access_token: &str) -> ApiResult<GuildLists> {
+    let user_id = get_user_id(access_token).await?;
+
+    let options = GuildRepositoryOptions(user_id, AccessToken::Bearer(access_token.into()));
+    get_user_guild_lists(&options).await
+}
+
+/// What a user may do in a guild's dashboard, ordered from least to most access.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLevel {
+    /// Any member of a guild the bot is in, may only read.
+    Viewer,
+    /// The owner or members with MANAGE_GUILD or ADMINISTRATOR.
+    Manager,
+}
+
+impl AccessLevel {
+    pub fn of(guild: &Guild) -> Self {
+        if guild.can_manage() {
+            AccessLevel::Manager
+        } else {
+            AccessLevel::Viewer
+        }
+    }
+}
+
+type ValidationResult = ApiResult<ValidationInfo>;
 pub struct ValidationInfo {
     pub guild_id: u64,
-    pub access_token: String,
 }
 
-pub struct Validator;
+pub struct Validator {
+    required: AccessLevel,
+}
+
 impl Validator {
     pub fn new() -> Self {
-        Self
+        Self {
+            required: AccessLevel::Viewer,
+        }
+    }
+
+    /// Rejects users below the access level with a 403.
+    pub fn require(mut self, access_level: AccessLevel) -> Self {
+        self.required = access_level;
+        self
     }
 
     pub async fn validate(&self, req: &HttpRequest) -> ValidationResult {
-        let access_token = parse_access_token(&req).ok_or("No access token found.")?;
+        let access_token = get_access_token(req).await?;
         let guild_id = req
             .match_info()
             .get("guild_id")
-            .ok_or("No guild id passed.")?;
-
-        match get_allowed_guilds(&access_token).await {
-            Ok(guilds) => {
-                if guilds.iter().any(|g| g.id == guild_id) {
-                    return Ok(ValidationInfo {
-                        guild_id: guild_id.parse::<u64>().unwrap(),
-                        access_token,
-                    });
-                }
-            }
-            Err(err) => println!("{}", err),
+            .ok_or_else(|| ApiError::BadRequest("No guild id passed.".into()))?;
+        let parsed_guild_id = guild_id
+            .parse::<u64>()
+            .map_err(|_| ApiError::BadRequest("Invalid guild id.".into()))?;
+
+        let guilds = get_allowed_guilds(&access_token).await?;
+        let guild = guilds
+            .iter()
+            .find(|g| g.id == guild_id)
+            .ok_or_else(|| ApiError::Forbidden("Guild not permitted.".into()))?;
+
+        let access_level = AccessLevel::of(guild);
+        if access_level < self.required {
+            return Err(ApiError::Forbidden(
+                "Managing this guild requires the Manage Server permission.".into(),
+            ));
         }
 
-        return Err("Guild not permitted.".into());
+        Ok(ValidationInfo {
+            guild_id: parsed_guild_id,
+        })
     }
 }
@@ -1,79 +1,121 @@
-use std::env;
-
 use actix_cors::Cors;
 use actix_web::http;
 use actix_web::web;
 use actix_web::App;
 use actix_web::HttpServer;
+mod config;
 mod discord;
 mod helpers;
 mod oauth;
 mod polls;
 use discord::routes::get_mutual_guilds;
 use oauth::routes::authenticate;
-use oauth::routes::reauthenticate;
+use oauth::routes::authenticate_legacy;
+use oauth::routes::bot_invite_url;
+use oauth::routes::logout;
 use oauth::routes::oauth_url;
+use oauth::routes::reauthenticate;
+use oauth::routes::reauthenticate_legacy;
+use polls::executor::spawn_executor;
+use polls::routes::delete_poll;
+use polls::routes::execute_poll_change;
 use polls::routes::get_available_poll_changes;
+use polls::routes::get_poll;
 use polls::routes::get_poll_settings;
+use polls::routes::list_polls;
 use polls::routes::save_poll;
+use polls::routes::save_poll_settings;
+use polls::routes::update_poll;
 
+use crate::config::Config;
 use crate::discord::routes::get_all_members;
 use crate::discord::routes::get_all_roles;
 use crate::discord::routes::get_all_text_channels;
-use crate::helpers::caching::base::get_connection_redis;
-
-pub fn panic_on_missing_env() {
-    env::var("DISCORD_CLIENT_ID").expect("Expected DISCORD_CLIENT_ID in the environment");
-    env::var("DISCORD_CLIENT_SECRET").expect("Expected DISCORD_CLIENT_SECRET in the environment");
-    env::var("DISCORD_CLIENT_TOKEN").expect("Expected DISCORD_CLIENT_TOKEN in the environment");
-    env::var("CLIENT_URI").expect("Expected CLIENT_URI in the environment");
-    env::var("DB_HOST").expect("Expected DB_HOST in the environment");
-    env::var("DB_NAME").expect("Expected DB_NAME in the environment");
-    env::var("DB_PASSWORD").expect("Expected DB_PASSWORD in the environment");
-    env::var("DB_USER").expect("Expected DB_USER in the environment");
-}
+use crate::discord::routes::get_guilds;
+use crate::discord::routes::refresh_guild_cache;
+use crate::helpers::caching::base::CacheStore;
+use crate::helpers::database::base::Database;
+use crate::helpers::database::migrations::run_migrations;
+use crate::helpers::errors::ApiError;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    get_connection_redis().expect("Failed to load redis");
-    dotenv::dotenv().expect("Failed to load .env file");
-    panic_on_missing_env();
+    // Without a .env file the config is read from config.toml and the environment only.
+    dotenv::dotenv().ok();
+    let config = match Config::load() {
+        Ok(config) => config.install(),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    CacheStore::connect(config)
+        .expect("Failed to set up the cache")
+        .install();
+
+    let database = Database::connect(&config.database).expect("Failed to connect to database");
+    run_migrations(&database).expect("Failed to run database migrations");
+    spawn_executor(database.clone());
 
-    HttpServer::new(|| {
+    HttpServer::new(move || {
         App::new()
+            .data(database.clone())
+            .data(config.clone())
+            .app_data(web::JsonConfig::default().error_handler(|err, _| {
+                ApiError::BadRequest(format!("Invalid JSON body: {}", err)).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|err, _| {
+                ApiError::BadRequest(format!("Invalid query: {}", err)).into()
+            }))
+            .app_data(web::PathConfig::default().error_handler(|err, _| {
+                ApiError::BadRequest(format!("Invalid path: {}", err)).into()
+            }))
             .wrap(
-                Cors::default()
-                    .allowed_methods(vec!["GET", "POST"])
-                    .allowed_origin(
-                        &env::var("CLIENT_URI").expect("Expected CLIENT_URI in the environment"),
-                    )
+                config
+                    .server
+                    .cors_origins
+                    .iter()
+                    .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+                    .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
                     .allowed_header(http::header::CONTENT_TYPE)
-                    .allowed_header(http::header::AUTHORIZATION),
+                    .supports_credentials(),
             )
             .service(
                 web::scope("/api")
                     .service(
                         web::scope("/discord")
                             .service(get_mutual_guilds)
+                            .service(get_guilds)
                             .service(get_all_members)
                             .service(get_all_text_channels)
-                            .service(get_all_roles),
+                            .service(get_all_roles)
+                            .service(refresh_guild_cache),
                     )
                     .service(
                         web::scope("/oauth")
                             .service(oauth_url)
+                            .service(bot_invite_url)
                             .service(authenticate)
-                            .service(reauthenticate),
+                            .service(authenticate_legacy)
+                            .service(reauthenticate)
+                            .service(reauthenticate_legacy)
+                            .service(logout),
                     )
                     .service(
                         web::scope("/polls")
                             .service(save_poll)
+                            .service(list_polls)
+                            .service(get_poll)
+                            .service(update_poll)
+                            .service(delete_poll)
+                            .service(execute_poll_change)
                             .service(get_available_poll_changes)
-                            .service(get_poll_settings),
+                            .service(get_poll_settings)
+                            .service(save_poll_settings),
                     ),
             )
     })
-    .bind("127.0.0.1:8080")?
+    .bind(&config.server.bind_address)?
     .run()
     .await
 }
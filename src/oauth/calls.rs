@@ -1,20 +1,30 @@
 use std::collections::HashMap;
-use std::env;
 
 use actix_web::http::HeaderValue;
 use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+
+use crate::config::get_config;
+use crate::config::Config;
+use crate::discord::base_api::get_http_client;
+use crate::discord::base_api::TokenKind;
+use crate::helpers::errors::ApiError;
+use crate::helpers::errors::ApiResult;
 
 use super::models::AccessTokenResponse;
 use super::models::GrantType;
 
-pub async fn token_call(grant_type: GrantType) -> Result<AccessTokenResponse, String> {
+/// Where Discord sends the browser back to after a login, it has to match the login url's.
+pub fn get_redirect_uri(config: &Config) -> String {
+    format!("{}/authenticate", config.server.client_uri)
+}
+
+pub async fn token_call(grant_type: GrantType) -> ApiResult<AccessTokenResponse> {
+    let config = get_config();
     let mut params: HashMap<&str, String> = HashMap::new();
-    params.insert("client_id", env::var("DISCORD_CLIENT_ID").unwrap());
-    params.insert("client_secret", env::var("DISCORD_CLIENT_SECRET").unwrap());
-    params.insert(
-        "redirect_uri",
-        format!("{}/authenticate", env::var("CLIENT_URI").unwrap()),
-    );
+    params.insert("client_id", config.discord.client_id.to_string());
+    params.insert("client_secret", config.discord.client_secret.clone());
+    params.insert("redirect_uri", get_redirect_uri(config));
     params.insert("grant_type", grant_type.to_string());
 
     match grant_type {
@@ -26,8 +36,8 @@ pub async fn token_call(grant_type: GrantType) -> Result<AccessTokenResponse, St
         }
     };
 
-    let response = reqwest::Client::new()
-        .post("https://discord.com/api/oauth2/token")
+    let response = get_http_client()
+        .post(&format!("{}/oauth2/token", config.discord.base_url))
         .form(&params)
         .header(
             CONTENT_TYPE,
@@ -35,18 +45,48 @@ pub async fn token_call(grant_type: GrantType) -> Result<AccessTokenResponse, St
         )
         .send()
         .await
-        .map_err(|e| format!("{}", e))?;
-
-    match response.error_for_status_ref() {
-        Ok(_) => {
-            let json = response
-                .json::<AccessTokenResponse>()
-                .await
-                .map_err(|e| format!("{}", e))?;
-            return Ok(json);
-        }
-        Err(_) => {
-            return Err(response.text().await.map_err(|e| format!("{}", e))?);
-        }
+        .map_err(ApiError::from_reqwest)?;
+
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        let body = response.json::<serde_json::Value>().await.ok();
+        // An invalid or expired code or refresh token is answered with a 400 `invalid_grant`.
+        return Err(match status {
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED => ApiError::Unauthorized(
+                body.as_ref()
+                    .and_then(|b| b["error_description"].as_str())
+                    .unwrap_or("Discord rejected the grant.")
+                    .into(),
+            ),
+            _ => ApiError::from_discord(status, body, TokenKind::User),
+        });
+    }
+
+    response
+        .json::<AccessTokenResponse>()
+        .await
+        .map_err(ApiError::from_reqwest)
+}
+
+/// Revokes an access or refresh token, Discord answers 200 even for unknown tokens.
+pub async fn revoke_call(token: &str) -> ApiResult<()> {
+    let config = get_config();
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("client_id", config.discord.client_id.to_string());
+    params.insert("client_secret", config.discord.client_secret.clone());
+    params.insert("token", token.into());
+
+    let response = get_http_client()
+        .post(&format!("{}/oauth2/token/revoke", config.discord.base_url))
+        .form(&params)
+        .send()
+        .await
+        .map_err(ApiError::from_reqwest)?;
+
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        let body = response.json::<serde_json::Value>().await.ok();
+        return Err(ApiError::from_discord(status, body, TokenKind::User));
     }
+    Ok(())
 }
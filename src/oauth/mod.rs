@@ -1,3 +1,4 @@
 pub mod calls;
 pub mod models;
 pub mod routes;
+pub mod session;
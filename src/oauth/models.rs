@@ -1,13 +1,24 @@
 use serde::Deserialize;
 use serde::Serialize;
 use strum_macros::Display;
+use strum_macros::EnumString;
 
-#[derive(Display, Serialize, Deserialize, Debug)]
+use crate::discord::permissions::Permissions;
+
+#[derive(Display, EnumString, Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[strum(serialize_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum OauthScope {
     Identify,
+    Email,
     Guilds,
+    #[strum(serialize = "guilds.members.read")]
+    #[serde(rename = "guilds.members.read")]
+    GuildsMembersRead,
+    Bot,
+    #[strum(serialize = "applications.commands")]
+    #[serde(rename = "applications.commands")]
+    ApplicationsCommands,
 }
 
 #[derive(Display, Serialize, Deserialize)]
@@ -22,6 +33,37 @@ pub struct OauthUrlSettings {
     pub client_id: u64,
     pub redirect_uri: String,
     pub response_type: ResponseType,
+    pub state: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OauthUrlResponse {
+    pub url: String,
+    /// Has to be passed back to `authenticate` along with the code.
+    pub state: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BotInviteUrlResponse {
+    pub url: String,
+    /// The permission integer the invite asks for.
+    pub permissions: Permissions,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuthenticateBody {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReauthenticateBody {
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuthenticateQuery {
+    pub state: Option<String>,
 }
 
 #[derive(Display, Serialize, Deserialize, Debug)]
@@ -40,3 +82,18 @@ pub struct AccessTokenResponse {
     pub refresh_token: String,
     pub scope: String,
 }
+
+/// A dashboard session, the Discord tokens are encrypted and never leave the server.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoredSession {
+    pub user_id: u64,
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Unix timestamp at which Discord expires the access token.
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionResponse {
+    pub user_id: String,
+}
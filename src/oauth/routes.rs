@@ -1,95 +1,294 @@
-use std::collections::hash_map::DefaultHasher;
-use std::env;
-use std::hash::Hash;
-use std::hash::Hasher;
-
 use actix_web::get;
+use actix_web::http::header::HeaderName;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::header::WARNING;
+use actix_web::post;
+use actix_web::web;
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
-use actix_web::Responder;
+use actix_web::ResponseError;
 
+use crate::config::Config;
 use crate::discord::base_api::Callable;
 use crate::discord::calls::GetMe;
 use crate::discord::discord_base::AccessToken;
 use crate::discord::discord_base::DiscordCall;
+use crate::discord::models::parse_user_id;
+use crate::discord::permissions::Permissions;
 use crate::helpers::caching::base::Cache;
 use crate::helpers::caching::discord::AccessTokenHash;
+use crate::helpers::caching::discord::OauthState;
+use crate::helpers::caching::discord::OauthStateCache;
 use crate::helpers::caching::discord::UserIdCache;
+use crate::helpers::crypto::random_token;
+use crate::helpers::errors::ApiError;
+use crate::helpers::errors::ApiResult;
 use crate::oauth::models::OauthScope;
 use crate::oauth::models::ResponseType;
+use crate::polls::changes::get_executor_permissions;
 
+use super::calls::get_redirect_uri;
 use super::calls::token_call;
 use super::models::AccessTokenResponse;
+use super::models::AuthenticateBody;
+use super::models::AuthenticateQuery;
+use super::models::BotInviteUrlResponse;
 use super::models::GrantType;
+use super::models::OauthUrlResponse;
 use super::models::OauthUrlSettings;
+use super::models::ReauthenticateBody;
+use super::models::SessionResponse;
+use super::session::create_session;
+use super::session::end_session;
+use super::session::get_session_id;
+use super::session::removal_cookie;
+use super::session::session_cookie;
+use super::session::started_login;
+use super::session::state_cookie;
+use super::session::state_removal_cookie;
+
+fn format_scopes(scopes: &[OauthScope]) -> String {
+    scopes
+        .iter()
+        .map(|s| format!("{}", s))
+        .collect::<Vec<String>>()
+        .join("%20")
+}
+
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
 
 struct OauthController;
 impl OauthController {
     pub fn create_url(settings: OauthUrlSettings) -> String {
-        format!("https://discord.com/oauth2/authorize?client_id={}&redirect_uri={}&response_type={}&scope={}",
+        format!("https://discord.com/oauth2/authorize?client_id={}&redirect_uri={}&response_type={}&scope={}&state={}",
             settings.client_id,
-            settings.redirect_uri,
+            encode(&settings.redirect_uri),
             settings.response_type,
-            settings.scopes.iter().map(|s|format!("{}", s)).collect::<Vec<String>>().join("%20")
+            format_scopes(&settings.scopes),
+            encode(&settings.state)
+        )
+    }
+
+    /// Adds the bot to the guild, the guild is preselected and can't be changed in Discord.
+    pub fn create_bot_url(client_id: u64, guild_id: u64, permissions: Permissions) -> String {
+        format!("https://discord.com/oauth2/authorize?client_id={}&scope={}&permissions={}&guild_id={}&disable_guild_select=true",
+            client_id,
+            format_scopes(&[OauthScope::Bot]),
+            permissions.bits(),
+            guild_id
         )
     }
 }
 
-async fn store_oauth(response: &AccessTokenResponse) {
-    let mut hasher = DefaultHasher::new();
-    response.access_token.hash(&mut hasher);
+/// Invites the bot with the permissions the poll changes need.
+pub fn get_bot_invite_url(config: &Config, guild_id: u64) -> String {
+    OauthController::create_bot_url(
+        config.discord.client_id,
+        guild_id,
+        get_executor_permissions(),
+    )
+}
+
+/// The scopes asked for at login, the configured scopes add to the ones the dashboard needs.
+fn get_login_scopes(config: &Config) -> Vec<OauthScope> {
+    let mut scopes = vec![OauthScope::Identify, OauthScope::Guilds];
+    for scope in config.discord.oauth_scopes.iter() {
+        if !scopes.contains(scope) {
+            scopes.push(scope.clone());
+        }
+    }
+    scopes
+}
+
+/// Each state can be used once and only by the browser that started the login, so a callback
+/// can't be replayed, or forged by another site to log a victim into the attacker's account.
+async fn consume_state(req: &HttpRequest, state: Option<&str>) -> ApiResult<()> {
+    let consumed = match state {
+        Some(state) if started_login(req, state) => {
+            OauthStateCache::delete(OauthState(state.into())).await?
+        }
+        _ => false,
+    };
+    if consumed {
+        Ok(())
+    } else {
+        Err(ApiError::BadRequest(
+            "Invalid or expired login attempt, try logging in again.".into(),
+        ))
+    }
+}
+
+/// Remembers which user the access token belongs to and returns the user's id.
+async fn store_oauth(response: &AccessTokenResponse) -> ApiResult<u64> {
     let key = AccessTokenHash::new_with_expires_in(
-        &hasher.finish().to_string(),
-        response.expires_in.try_into().unwrap(),
+        &response.access_token,
+        response
+            .expires_in
+            .try_into()
+            .map_err(|_| ApiError::BadGateway {
+                message: format!(
+                    "Discord returned an invalid token lifetime `{}`.",
+                    response.expires_in
+                ),
+                details: None,
+            })?,
     );
 
     let call = DiscordCall::new(AccessToken::Bearer(response.access_token.clone()));
-    let result = call.call(GetMe).await;
+    let me = call.call(GetMe).await?;
+    let user_id = parse_user_id(&me.id)?;
+    UserIdCache::set(key, &user_id).await;
+    Ok(user_id)
+}
 
-    if let Ok(me) = result {
-        if let Ok(user_id) = me.id.parse::<u64>() {
-            UserIdCache::set(key, &user_id);
-        }
+/// Keeps the Discord tokens server side and hands the browser a session cookie instead.
+async fn start_session(grant_type: GrantType) -> ApiResult<HttpResponse> {
+    let response = token_call(grant_type).await?;
+    let user_id = store_oauth(&response).await?;
+    let session_id = create_session(user_id, &response).await?;
+
+    Ok(HttpResponse::Ok()
+        .cookie(session_cookie(session_id))
+        .json(SessionResponse {
+            user_id: user_id.to_string(),
+        }))
+}
+
+#[post("/authenticate")]
+pub async fn authenticate(req: HttpRequest, body: web::Json<AuthenticateBody>) -> HttpResponse {
+    let body = body.into_inner();
+    let mut response = match consume_state(&req, Some(&body.state)).await {
+        Ok(()) => match start_session(GrantType::AuthorizationCode(body.code)).await {
+            Ok(response) => response,
+            Err(err) => err.error_response(),
+        },
+        Err(err) => err.error_response(),
+    };
+    let _ = response.add_cookie(&state_removal_cookie());
+    response
+}
+
+#[post("/reauthenticate")]
+pub async fn reauthenticate(body: web::Json<ReauthenticateBody>) -> HttpResponse {
+    match start_session(GrantType::RefreshToken(body.into_inner().refresh_token)).await {
+        Ok(response) => response,
+        Err(err) => err.error_response(),
+    }
+}
+
+/// The GET routes carry secrets in their path, they only respond when
+/// `OAUTH_LEGACY_GET_ROUTES=true`. They behave like the POST routes apart from that.
+fn legacy_routes_enabled(config: &Config) -> bool {
+    config.discord.legacy_get_routes
+}
+
+fn deprecated(mut response: HttpResponse, replacement: &str) -> HttpResponse {
+    let warning = format!("299 - \"Deprecated, use POST {} instead\"", replacement);
+    if let Ok(value) = HeaderValue::from_str(&warning) {
+        response.headers_mut().insert(WARNING, value);
     }
+    response.headers_mut().insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    response
+}
+
+fn legacy_route_disabled(replacement: &str) -> HttpResponse {
+    ApiError::NotFound(format!("This route was removed, use POST {}.", replacement))
+        .error_response()
 }
 
 #[get("/authenticate/{code}")]
-pub async fn authenticate(req: HttpRequest) -> HttpResponse {
-    match req.match_info().get("code") {
-        Some(code) => match token_call(GrantType::AuthorizationCode(code.into())).await {
-            Ok(response) => {
-                store_oauth(&response).await;
-                HttpResponse::Ok().json(response)
-            }
-            Err(err) => HttpResponse::BadRequest().body(err),
-        },
-        None => HttpResponse::BadRequest().finish(),
+pub async fn authenticate_legacy(
+    req: HttpRequest,
+    query: web::Query<AuthenticateQuery>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let replacement = "/api/oauth/authenticate";
+    if !legacy_routes_enabled(&config) {
+        return legacy_route_disabled(replacement);
     }
+    let mut response = match consume_state(&req, query.state.as_deref()).await {
+        Ok(()) => match req.match_info().get("code") {
+            Some(code) => match start_session(GrantType::AuthorizationCode(code.into())).await {
+                Ok(response) => response,
+                Err(err) => err.error_response(),
+            },
+            None => ApiError::BadRequest("No code passed.".into()).error_response(),
+        },
+        Err(err) => err.error_response(),
+    };
+    let _ = response.add_cookie(&state_removal_cookie());
+    deprecated(response, replacement)
 }
 
 #[get("/reauthenticate/{refresh_token}")]
-pub async fn reauthenticate(req: HttpRequest) -> HttpResponse {
-    match req.match_info().get("refresh_token") {
-        Some(code) => match token_call(GrantType::RefreshToken(code.into())).await {
-            Ok(response) => {
-                store_oauth(&response).await;
-                HttpResponse::Ok().json(response)
-            }
-            Err(err) => HttpResponse::BadRequest().body(err),
-        },
-        None => HttpResponse::BadRequest().finish(),
+pub async fn reauthenticate_legacy(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    let replacement = "/api/oauth/reauthenticate";
+    if !legacy_routes_enabled(&config) {
+        return legacy_route_disabled(replacement);
     }
+
+    let response = match req.match_info().get("refresh_token") {
+        Some(code) => match start_session(GrantType::RefreshToken(code.into())).await {
+            Ok(response) => response,
+            Err(err) => err.error_response(),
+        },
+        None => ApiError::BadRequest("No refresh token passed.".into()).error_response(),
+    };
+    deprecated(response, replacement)
+}
+
+/// Signs out: forgets everything cached for the session, revokes its Discord tokens and clears
+/// the session cookie. The cookie is cleared even when Discord couldn't be reached.
+#[post("/logout")]
+pub async fn logout(req: HttpRequest) -> HttpResponse {
+    let mut response = match get_session_id(&req) {
+        Some(session_id) => match end_session(&session_id).await {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(err) => err.error_response(),
+        },
+        None => HttpResponse::NoContent().finish(),
+    };
+    let _ = response.add_cookie(&removal_cookie());
+    response
 }
 
 #[get("/url")]
-pub async fn oauth_url() -> impl Responder {
-    OauthController::create_url(OauthUrlSettings {
-        scopes: vec![OauthScope::Identify, OauthScope::Guilds],
-        client_id: env::var("DISCORD_CLIENT_ID")
-            .unwrap()
-            .parse::<u64>()
-            .unwrap(),
-        redirect_uri: format!("{}/authenticate", env::var("CLIENT_URI").unwrap()),
+pub async fn oauth_url(config: web::Data<Config>) -> HttpResponse {
+    let state = random_token();
+    if !OauthStateCache::set(OauthState(state.clone()), &true).await {
+        return ApiError::Unavailable("Couldn't start the login.".into()).error_response();
+    }
+
+    let url = OauthController::create_url(OauthUrlSettings {
+        scopes: get_login_scopes(&config),
+        client_id: config.discord.client_id,
+        redirect_uri: get_redirect_uri(&config),
         response_type: ResponseType::Code,
+        state: state.clone(),
+    });
+    HttpResponse::Ok()
+        .cookie(state_cookie(&state))
+        .json(OauthUrlResponse { url, state })
+}
+
+#[get("/bot_url/{guild_id}")]
+pub async fn bot_invite_url(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    let guild_id = match req
+        .match_info()
+        .get("guild_id")
+        .and_then(|id| id.parse::<u64>().ok())
+    {
+        Some(guild_id) => guild_id,
+        None => return ApiError::BadRequest("Invalid guild id.".into()).error_response(),
+    };
+
+    HttpResponse::Ok().json(BotInviteUrlResponse {
+        url: get_bot_invite_url(&config, guild_id),
+        permissions: get_executor_permissions(),
     })
 }
@@ -0,0 +1,182 @@
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use actix_web::cookie::Cookie;
+use actix_web::cookie::SameSite;
+use actix_web::HttpMessage;
+use actix_web::HttpRequest;
+
+use crate::config::get_config;
+use crate::helpers::caching::base::Cache;
+use crate::helpers::caching::discord::GuildsCache;
+use crate::helpers::caching::discord::SessionCache;
+use crate::helpers::caching::discord::SessionId;
+use crate::helpers::caching::discord::UserId;
+use crate::helpers::caching::discord::UserIdCache;
+use crate::helpers::crypto::decrypt;
+use crate::helpers::crypto::encrypt;
+use crate::helpers::crypto::keyed_hash;
+use crate::helpers::crypto::random_token;
+use crate::helpers::errors::ApiError;
+use crate::helpers::errors::ApiResult;
+
+use super::calls::revoke_call;
+use super::calls::token_call;
+use super::models::AccessTokenResponse;
+use super::models::GrantType;
+use super::models::StoredSession;
+
+pub const SESSION_COOKIE: &str = "session";
+/// Holds the hash of the state of the login the browser started.
+pub const STATE_COOKIE: &str = "oauth_state";
+
+/// Access tokens are refreshed this many seconds before Discord expires them.
+const REFRESH_MARGIN: u64 = 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn to_stored_session(user_id: u64, response: &AccessTokenResponse) -> ApiResult<StoredSession> {
+    Ok(StoredSession {
+        user_id,
+        access_token: encrypt(&response.access_token).map_err(ApiError::Internal)?,
+        refresh_token: encrypt(&response.refresh_token).map_err(ApiError::Internal)?,
+        expires_at: now() + response.expires_in.max(0) as u64,
+    })
+}
+
+async fn store_session(session_id: &str, session: &StoredSession) -> ApiResult<()> {
+    if SessionCache::set(SessionId(session_id.into()), session).await {
+        Ok(())
+    } else {
+        Err(ApiError::Unavailable("Couldn't store the session.".into()))
+    }
+}
+
+/// Starts a session for freshly exchanged Discord tokens, returns the session id.
+pub async fn create_session(user_id: u64, response: &AccessTokenResponse) -> ApiResult<String> {
+    let session_id = random_token();
+    store_session(&session_id, &to_stored_session(user_id, response)?).await?;
+    Ok(session_id)
+}
+
+fn build_cookie(name: &'static str, value: String, max_age: time::Duration) -> Cookie<'static> {
+    let secure = get_config().server.client_uri.starts_with("https://");
+    Cookie::build(name, value)
+        .path("/")
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Lax)
+        .max_age(max_age)
+        .finish()
+}
+
+/// The HttpOnly cookie holding the session id, only sent over https when the client is.
+pub fn session_cookie(session_id: String) -> Cookie<'static> {
+    let max_age = get_config().cache.session_ttl;
+    build_cookie(
+        SESSION_COOKIE,
+        session_id,
+        time::Duration::seconds(max_age as i64),
+    )
+}
+
+/// A cookie that makes the browser drop the session cookie.
+pub fn removal_cookie() -> Cookie<'static> {
+    build_cookie(SESSION_COOKIE, String::new(), time::Duration::zero())
+}
+
+fn get_cookie_value(req: &HttpRequest, name: &str) -> Option<String> {
+    let cookie = req.cookie(name)?;
+    Some(cookie.value().to_string()).filter(|v| !v.is_empty())
+}
+
+pub fn get_session_id(req: &HttpRequest) -> Option<String> {
+    get_cookie_value(req, SESSION_COOKIE)
+}
+
+/// Ties a login state to the browser that asked for it, lives as long as the state.
+pub fn state_cookie(state: &str) -> Cookie<'static> {
+    let max_age = get_config().cache.oauth_state_ttl;
+    build_cookie(
+        STATE_COOKIE,
+        keyed_hash(state),
+        time::Duration::seconds(max_age as i64),
+    )
+}
+
+/// A cookie that makes the browser drop the state cookie.
+pub fn state_removal_cookie() -> Cookie<'static> {
+    build_cookie(STATE_COOKIE, String::new(), time::Duration::zero())
+}
+
+/// Whether the request comes from the browser that started the login with `state`.
+pub fn started_login(req: &HttpRequest, state: &str) -> bool {
+    get_cookie_value(req, STATE_COOKIE).is_some_and(|hash| hash == keyed_hash(state))
+}
+
+fn no_session() -> ApiError {
+    ApiError::Unauthorized("No valid session, log in again.".into())
+}
+
+/// Exchanges the refresh token for new tokens and stores them in the existing session.
+async fn refresh_session(session_id: &str, session: StoredSession) -> ApiResult<String> {
+    let refresh_token = decrypt(&session.refresh_token).map_err(|_| no_session())?;
+    match token_call(GrantType::RefreshToken(refresh_token)).await {
+        Ok(response) => {
+            store_session(session_id, &to_stored_session(session.user_id, &response)?).await?;
+            Ok(response.access_token)
+        }
+        Err(ApiError::Unauthorized(_)) => {
+            // A concurrent request may have refreshed first, which invalidates our refresh token.
+            match SessionCache::get(SessionId(session_id.into())).await? {
+                Some(current) if current.expires_at > now() + REFRESH_MARGIN => {
+                    decrypt(&current.access_token).map_err(|_| no_session())
+                }
+                _ => {
+                    SessionCache::delete(SessionId(session_id.into())).await?;
+                    Err(no_session())
+                }
+            }
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The Discord access token of the request's session, refreshed when it is about to expire.
+pub async fn get_access_token(req: &HttpRequest) -> ApiResult<String> {
+    let session_id = get_session_id(req).ok_or_else(no_session)?;
+    let session = SessionCache::get(SessionId(session_id.clone()))
+        .await?
+        .ok_or_else(no_session)?;
+
+    if session.expires_at <= now() + REFRESH_MARGIN {
+        return refresh_session(&session_id, session).await;
+    }
+    decrypt(&session.access_token).map_err(|_| no_session())
+}
+
+/// Forgets the session and what was cached for its user, then revokes its Discord tokens.
+pub async fn end_session(session_id: &str) -> ApiResult<()> {
+    let session = match SessionCache::get(SessionId(session_id.into())).await? {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+    SessionCache::delete(SessionId(session_id.into())).await?;
+    GuildsCache::delete(UserId(session.user_id)).await?;
+
+    let access_token = decrypt(&session.access_token).ok();
+    let refresh_token = decrypt(&session.refresh_token).ok();
+    if let Some(access_token) = &access_token {
+        UserIdCache::delete_for_token(access_token).await?;
+    }
+
+    for token in [access_token, refresh_token].iter().flatten() {
+        revoke_call(token).await?;
+    }
+    Ok(())
+}
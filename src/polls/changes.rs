@@ -0,0 +1,118 @@
+use serde::Deserialize;
+use serde::Serialize;
+use strum_macros::Display;
+use strum_macros::EnumString;
+
+use crate::discord::permissions::Permissions;
+use crate::helpers::caching::discord::GuildResource;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ChangeKeyKind {
+    Member,
+    Channel,
+    String,
+    Role,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ChangeValueKind {
+    Member,
+    String,
+    None,
+    Role,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChangeIdentifier {
+    pub value: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChangeInfo {
+    pub identifier: ChangeIdentifier,
+    pub key_kind: ChangeKeyKind,
+    pub value_kind: ChangeValueKind,
+    /// What the bot needs in the guild, or in the channel for changes keyed by a channel.
+    #[serde(default)]
+    pub required_permissions: Permissions,
+    /// Cached guild data that is stale once the change was applied.
+    #[serde(default)]
+    pub invalidates: Vec<GuildResource>,
+}
+
+/// The change a poll applies when it passes, `name` refers to a `ChangeIdentifier` value.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollChange {
+    pub name: String,
+    pub key: String,
+    pub value: Option<String>,
+}
+
+#[derive(Display, EnumString, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeStatus {
+    #[default]
+    Pending,
+    Running,
+    Applied,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangeOutcome {
+    pub status: ChangeStatus,
+    pub error: Option<String>,
+}
+
+/// Every change a poll can apply to a guild when it passes.
+pub fn get_available_changes() -> Vec<ChangeInfo> {
+    vec![
+        ChangeInfo {
+            identifier: ChangeIdentifier {
+                value: "create_channel".into(),
+                name: "Create channel".into(),
+            },
+            key_kind: ChangeKeyKind::String,
+            value_kind: ChangeValueKind::None,
+            required_permissions: Permissions::MANAGE_CHANNELS,
+            invalidates: vec![GuildResource::Channels],
+        },
+        ChangeInfo {
+            identifier: ChangeIdentifier {
+                value: "delete_channel".into(),
+                name: "Delete channel".into(),
+            },
+            key_kind: ChangeKeyKind::Channel,
+            value_kind: ChangeValueKind::None,
+            required_permissions: Permissions::VIEW_CHANNEL | Permissions::MANAGE_CHANNELS,
+            invalidates: vec![GuildResource::Channels],
+        },
+        ChangeInfo {
+            identifier: ChangeIdentifier {
+                value: "assign_role".into(),
+                name: "Assign role".into(),
+            },
+            key_kind: ChangeKeyKind::Role,
+            value_kind: ChangeValueKind::Member,
+            required_permissions: Permissions::MANAGE_ROLES,
+            invalidates: vec![GuildResource::Members],
+        },
+    ]
+}
+
+pub fn get_change_info(name: &str) -> Option<ChangeInfo> {
+    get_available_changes()
+        .into_iter()
+        .find(|c| c.identifier.value == name)
+}
+
+/// Everything the bot needs to apply any of the available changes.
+pub fn get_executor_permissions() -> Permissions {
+    get_available_changes()
+        .iter()
+        .fold(Permissions::empty(), |permissions, change| {
+            permissions | change.required_permissions
+        })
+}
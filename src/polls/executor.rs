@@ -0,0 +1,184 @@
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::rt;
+
+use crate::discord::base_api::Callable;
+use crate::discord::calls::AddMemberRole;
+use crate::discord::calls::ChannelKind;
+use crate::discord::calls::CreateChannel;
+use crate::discord::calls::CreateChannelBody;
+use crate::discord::calls::DeleteChannel;
+use crate::discord::discord_base::DiscordCall;
+use crate::helpers::caching::discord::invalidate_guild;
+use crate::helpers::database::base::Database;
+use crate::helpers::errors::ApiError;
+use crate::helpers::repositories::discord::ChannelRepository;
+use crate::helpers::repositories::discord::ChannelRepositoryOptions;
+use crate::helpers::repositories::discord::Repository;
+
+use super::changes::get_change_info;
+use super::changes::ChangeOutcome;
+use super::changes::ChangeStatus;
+use super::changes::PollChange;
+use super::models::Poll;
+use super::storage::PollStore;
+
+const EXECUTOR_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum ChangeError {
+    MissingPermissions,
+    TargetNotFound(&'static str),
+    Invalid(String),
+    Discord(String),
+}
+
+impl fmt::Display for ChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeError::MissingPermissions => {
+                write!(f, "The bot is missing permissions to apply this change.")
+            }
+            ChangeError::TargetNotFound(target) => write!(f, "The {} no longer exists.", target),
+            ChangeError::Invalid(message) => write!(f, "{}", message),
+            ChangeError::Discord(message) => write!(f, "Discord error: {}", message),
+        }
+    }
+}
+
+impl ChangeError {
+    /// Turns a failed Discord call into a failure the dashboard can explain.
+    fn from_discord(err: ApiError, target: &'static str) -> Self {
+        match err {
+            // Discord answers a bot without the permissions with 403.
+            ApiError::BadGateway {
+                details: Some(details),
+                ..
+            } if details["status"] == 403 => ChangeError::MissingPermissions,
+            ApiError::NotFound(_) => ChangeError::TargetNotFound(target),
+            err => ChangeError::Discord(err.to_string()),
+        }
+    }
+}
+
+fn parse_id(value: &str, target: &str) -> Result<u64, ChangeError> {
+    value
+        .parse::<u64>()
+        .map_err(|_| ChangeError::Invalid(format!("Invalid {} id `{}`.", target, value)))
+}
+
+/// Performs a single change against the guild using the bot token, `reason` ends up in the
+/// guild's audit log.
+pub async fn apply_change(
+    guild_id: u64,
+    change: &PollChange,
+    reason: &str,
+) -> Result<(), ChangeError> {
+    let call = DiscordCall::bot();
+    let reason = Some(reason.to_string());
+    match change.name.as_str() {
+        "create_channel" => call
+            .call(CreateChannel {
+                guild_id,
+                body: CreateChannelBody {
+                    name: change.key.clone(),
+                    kind: ChannelKind::GuildText,
+                },
+                reason,
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| ChangeError::from_discord(e, "guild")),
+        "delete_channel" => {
+            let channel_id = parse_id(&change.key, "channel")?;
+            // Channels are deleted by id alone, make sure it still belongs to this guild.
+            let channels = ChannelRepository::get_uncached(&ChannelRepositoryOptions(
+                guild_id,
+                ChannelKind::GuildText,
+            ))
+            .await
+            .map_err(|e| ChangeError::Discord(e.to_string()))?;
+            if !channels.iter().any(|c| c.id == change.key) {
+                return Err(ChangeError::TargetNotFound("channel"));
+            }
+
+            call.call(DeleteChannel { channel_id, reason })
+                .await
+                .map(|_| ())
+                .map_err(|e| ChangeError::from_discord(e, "channel"))
+        }
+        "assign_role" => {
+            let role_id = parse_id(&change.key, "role")?;
+            let user_id = parse_id(change.value.as_deref().unwrap_or_default(), "member")?;
+            call.call(AddMemberRole {
+                guild_id,
+                user_id,
+                role_id,
+                reason,
+            })
+            .await
+            .map_err(|e| ChangeError::from_discord(e, "role or member"))
+        }
+        other => Err(ChangeError::Invalid(format!("Unknown change `{}`.", other))),
+    }
+}
+
+/// Applies the change of a passed poll and records the outcome on the poll.
+/// Returns `None` when there is nothing to do or another run already claimed the change.
+pub async fn execute_change(
+    database: &Database,
+    guild_id: u64,
+    poll: &Poll,
+    from: &[ChangeStatus],
+) -> Result<Option<ChangeOutcome>, String> {
+    let change = match &poll.change {
+        Some(change) => change,
+        None => return Ok(None),
+    };
+    if !PollStore::claim_change(database, guild_id, poll.id, from).await? {
+        return Ok(None);
+    }
+
+    let reason = format!("Poll #{} passed: {}", poll.id, poll.question);
+    let outcome = match apply_change(guild_id, change, &reason).await {
+        Ok(()) => {
+            if let Some(info) = get_change_info(&change.name) {
+                invalidate_guild(guild_id, &info.invalidates).await;
+            }
+            ChangeOutcome {
+                status: ChangeStatus::Applied,
+                error: None,
+            }
+        }
+        Err(err) => ChangeOutcome {
+            status: ChangeStatus::Failed,
+            error: Some(err.to_string()),
+        },
+    };
+    PollStore::set_change_outcome(database, guild_id, poll.id, outcome.clone()).await?;
+    Ok(Some(outcome))
+}
+
+pub async fn execute_pending_changes(database: &Database) -> Result<(), String> {
+    for (guild_id, poll) in PollStore::get_pending_changes(database).await? {
+        if let Err(err) = execute_change(database, guild_id, &poll, &[ChangeStatus::Pending]).await
+        {
+            eprintln!("Failed to execute change of poll {}: {}", poll.id, err);
+        }
+    }
+    Ok(())
+}
+
+/// Periodically applies the changes of polls that have passed since the last run.
+pub fn spawn_executor(database: Database) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(EXECUTOR_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = execute_pending_changes(&database).await {
+                eprintln!("Poll change executor failed: {}", err);
+            }
+        }
+    });
+}
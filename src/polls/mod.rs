@@ -1,2 +1,6 @@
+pub mod changes;
+pub mod executor;
 pub mod models;
 pub mod routes;
+pub mod storage;
+pub mod validation;
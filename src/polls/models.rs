@@ -1,24 +1,95 @@
 use serde::Deserialize;
 use serde::Serialize;
+use strum_macros::Display;
+use strum_macros::EnumString;
+
+use super::changes::get_available_changes;
+use super::changes::ChangeOutcome;
+use super::changes::PollChange;
+
+#[derive(Display, EnumString, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PollStatus {
+    #[default]
+    Draft,
+    Active,
+    Passed,
+    Failed,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PollOption {
-    positive: bool,
-    value: String,
+    pub positive: bool,
+    pub value: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Poll {
-    id: i32,
-    question: String,
-    channel_id: u64,
-    result_channel_id: Option<u64>,
-    pin: bool,
-    mention_role: bool,
-    delete_after_results: bool,
-    custom: bool,
-    role_id_needed: Option<u64>,
-    vote_percentage_needed_to_pass: i16,
-    max_votes_per_user: i16,
-    options: Vec<PollOption>,
+    #[serde(default)]
+    pub id: i32,
+    #[serde(default)]
+    pub status: PollStatus,
+    pub question: String,
+    pub channel_id: u64,
+    pub result_channel_id: Option<u64>,
+    pub pin: bool,
+    pub mention_role: bool,
+    pub delete_after_results: bool,
+    pub custom: bool,
+    pub role_id_needed: Option<u64>,
+    pub vote_percentage_needed_to_pass: i16,
+    pub max_votes_per_user: i16,
+    pub options: Vec<PollOption>,
+    #[serde(default)]
+    pub change: Option<PollChange>,
+    /// Filled in by the executor once the poll has passed, never read from requests.
+    #[serde(default, skip_deserializing)]
+    pub change_outcome: Option<ChangeOutcome>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PollPage {
+    pub polls: Vec<Poll>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollSettings {
+    /// Channels polls may be posted in, an empty list allows every text channel.
+    pub allowed_channels: Vec<String>,
+    pub allowed_changes: Vec<String>,
+}
+
+fn dedup(values: Vec<String>) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::with_capacity(values.len());
+    for value in values {
+        if !unique.contains(&value) {
+            unique.push(value);
+        }
+    }
+    unique
+}
+
+impl PollSettings {
+    pub fn without_duplicates(self) -> Self {
+        Self {
+            allowed_channels: dedup(self.allowed_channels),
+            allowed_changes: dedup(self.allowed_changes),
+        }
+    }
+}
+
+impl Default for PollSettings {
+    fn default() -> Self {
+        Self {
+            allowed_channels: Vec::new(),
+            allowed_changes: get_available_changes()
+                .into_iter()
+                .map(|c| c.identifier.value)
+                .collect(),
+        }
+    }
 }
@@ -1,105 +1,304 @@
+use crate::helpers::database::base::Database;
+use crate::helpers::errors::ApiError;
+use crate::helpers::validator::AccessLevel;
 use crate::helpers::validator::Validator;
 
+use super::changes::get_available_changes;
+use super::changes::ChangeStatus;
+use super::executor::execute_change;
 use super::models::Poll;
+use super::models::PollSettings;
+use super::models::PollStatus;
+use super::storage::PollFilter;
+use super::storage::PollSettingsStore;
+use super::storage::PollStore;
+use super::storage::PollUpdate;
+use super::validation::validate_poll;
+use super::validation::validate_poll_settings;
+use actix_web::delete;
 use actix_web::get;
 use actix_web::post;
+use actix_web::put;
 use actix_web::web;
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
-use actix_web::Responder;
+use actix_web::ResponseError;
 
-#[post("/save")]
-pub async fn save_poll(poll: web::Json<Poll>) -> impl Responder {
-    println!("{:?}", poll);
-    format!("OK")
+/// Responds with the field errors of an invalid poll, or with the error that prevented validation.
+async fn check_poll(database: &Database, guild_id: u64, poll: &Poll) -> Result<(), HttpResponse> {
+    let settings = PollSettingsStore::get(database, guild_id)
+        .await
+        .map_err(|err| ApiError::Internal(err).error_response())?;
+
+    match validate_poll(guild_id, poll, &settings).await {
+        Ok(errors) if errors.is_empty() => Ok(()),
+        Ok(errors) => Err(ApiError::Validation(errors).error_response()),
+        Err(err) => Err(err.error_response()),
+    }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-pub struct PollSettings {
-    allowed_channels: Vec<String>,
-    allowed_changes: Vec<String>,
+#[post("/{guild_id}/save")]
+pub async fn save_poll(
+    req: HttpRequest,
+    database: web::Data<Database>,
+    poll: web::Json<Poll>,
+) -> HttpResponse {
+    match Validator::new()
+        .require(AccessLevel::Manager)
+        .validate(&req)
+        .await
+    {
+        Ok(validation) => {
+            let poll = poll.into_inner();
+            if let Err(response) = check_poll(&database, validation.guild_id, &poll).await {
+                return response;
+            }
+
+            match PollStore::insert(&database, validation.guild_id, poll).await {
+                Ok(poll) => HttpResponse::Ok().json(poll),
+                Err(err) => ApiError::Internal(err).error_response(),
+            }
+        }
+        Err(err) => err.error_response(),
+    }
 }
 
-#[post("/save_settings")]
-pub async fn save_poll_settings(poll: web::Json<PollSettings>) -> impl Responder {
-    println!("{:?}", poll);
-    format!("OK")
+#[derive(serde::Deserialize, Debug)]
+pub struct PollListQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    /// Comma separated list of statuses, e.g. `draft,active`.
+    status: Option<String>,
 }
 
-#[get("/{guild_id}/get_settings")]
-pub async fn get_poll_settings(req: HttpRequest) -> HttpResponse {
-    match Validator::new().validate(&req).await {
-        Ok(_info) => HttpResponse::Ok().json(PollSettings {
-            allowed_channels: vec![
-                "906898585302499369".into(),
-                "912721488187117578".into(),
-                "906898600162906162".into(),
-            ],
-            allowed_changes: vec![
-                "create_channel".into(),
-                "delete_channel".into(),
-                "assign_role".into(),
-            ],
-        }),
-        Err(_) => HttpResponse::Unauthorized().finish(),
+impl PollListQuery {
+    const DEFAULT_PER_PAGE: i64 = 25;
+    const MAX_PER_PAGE: i64 = 100;
+
+    fn into_filter(self) -> Result<PollFilter, String> {
+        let statuses = match &self.status {
+            Some(value) => value
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| {
+                    s.parse::<PollStatus>()
+                        .map_err(|_| format!("Unknown poll status `{}`.", s))
+                })
+                .collect::<Result<Vec<PollStatus>, String>>()?,
+            None => Vec::new(),
+        };
+
+        let page = self.page.unwrap_or(1).max(1);
+        let per_page = self
+            .per_page
+            .unwrap_or(Self::DEFAULT_PER_PAGE)
+            .clamp(1, Self::MAX_PER_PAGE);
+        // The offset of the page has to fit the database's integers.
+        if (page - 1).checked_mul(per_page).is_none() {
+            return Err(format!("Page `{}` is out of range.", page));
+        }
+
+        Ok(PollFilter {
+            statuses,
+            page,
+            per_page,
+        })
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-pub enum ChangeKeyKind {
-    Member,
-    Channel,
-    String,
-    Role,
+#[get("/{guild_id}/list")]
+pub async fn list_polls(
+    req: HttpRequest,
+    database: web::Data<Database>,
+    query: web::Query<PollListQuery>,
+) -> HttpResponse {
+    match Validator::new().validate(&req).await {
+        Ok(validation) => match query.into_inner().into_filter() {
+            Ok(filter) => match PollStore::list(&database, validation.guild_id, filter).await {
+                Ok(page) => HttpResponse::Ok().json(page),
+                Err(err) => ApiError::Internal(err).error_response(),
+            },
+            Err(err) => ApiError::BadRequest(err).error_response(),
+        },
+        Err(err) => err.error_response(),
+    }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-pub enum ChangeValueKind {
-    Member,
-    String,
-    None,
-    Role,
+fn parse_poll_id(req: &HttpRequest) -> Option<i32> {
+    req.match_info().get("poll_id")?.parse::<i32>().ok()
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-pub struct ChangeIdentifier {
-    pub value: String,
-    pub name: String,
+#[get("/{guild_id}/get/{poll_id}")]
+pub async fn get_poll(req: HttpRequest, database: web::Data<Database>) -> HttpResponse {
+    match Validator::new().validate(&req).await {
+        Ok(validation) => match parse_poll_id(&req) {
+            Some(poll_id) => match PollStore::get(&database, validation.guild_id, poll_id).await {
+                Ok(Some(poll)) => HttpResponse::Ok().json(poll),
+                Ok(None) => ApiError::NotFound("Poll not found.".into()).error_response(),
+                Err(err) => ApiError::Internal(err).error_response(),
+            },
+            None => ApiError::BadRequest("Invalid poll id.".into()).error_response(),
+        },
+        Err(err) => err.error_response(),
+    }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-pub struct ChangeInfo {
-    pub identifier: ChangeIdentifier,
-    pub key_kind: ChangeKeyKind,
-    pub value_kind: ChangeValueKind,
+#[put("/{guild_id}/update/{poll_id}")]
+pub async fn update_poll(
+    req: HttpRequest,
+    database: web::Data<Database>,
+    poll: web::Json<Poll>,
+) -> HttpResponse {
+    match Validator::new()
+        .require(AccessLevel::Manager)
+        .validate(&req)
+        .await
+    {
+        Ok(validation) => match parse_poll_id(&req) {
+            Some(poll_id) => {
+                let poll = poll.into_inner();
+                if let Err(response) = check_poll(&database, validation.guild_id, &poll).await {
+                    return response;
+                }
+
+                match PollStore::update(&database, validation.guild_id, poll_id, poll).await {
+                    Ok(PollUpdate::Updated(poll)) => HttpResponse::Ok().json(poll),
+                    Ok(PollUpdate::NotFound) => {
+                        ApiError::NotFound("Poll not found.".into()).error_response()
+                    }
+                    Ok(PollUpdate::NotDraft) => ApiError::Conflict(
+                        "Only polls that are still a draft can be updated.".into(),
+                    )
+                    .error_response(),
+                    Err(err) => ApiError::Internal(err).error_response(),
+                }
+            }
+            None => ApiError::BadRequest("Invalid poll id.".into()).error_response(),
+        },
+        Err(err) => err.error_response(),
+    }
 }
 
-#[get("/get_available_changes")]
-pub async fn get_available_poll_changes(_req: HttpRequest) -> HttpResponse {
-    HttpResponse::Ok().json(vec![
-        ChangeInfo {
-            identifier: ChangeIdentifier {
-                value: "create_channel".into(),
-                name: "Create channel".into(),
-            },
-            key_kind: ChangeKeyKind::String,
-            value_kind: ChangeValueKind::None,
+#[delete("/{guild_id}/delete/{poll_id}")]
+pub async fn delete_poll(req: HttpRequest, database: web::Data<Database>) -> HttpResponse {
+    match Validator::new()
+        .require(AccessLevel::Manager)
+        .validate(&req)
+        .await
+    {
+        Ok(validation) => match parse_poll_id(&req) {
+            Some(poll_id) => {
+                match PollStore::delete(&database, validation.guild_id, poll_id).await {
+                    Ok(true) => HttpResponse::NoContent().finish(),
+                    Ok(false) => ApiError::NotFound("Poll not found.".into()).error_response(),
+                    Err(err) => ApiError::Internal(err).error_response(),
+                }
+            }
+            None => ApiError::BadRequest("Invalid poll id.".into()).error_response(),
         },
-        ChangeInfo {
-            identifier: ChangeIdentifier {
-                value: "delete_channel".into(),
-                name: "Delete channel".into(),
-            },
-            key_kind: ChangeKeyKind::Channel,
-            value_kind: ChangeValueKind::None,
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Retries the change of a passed poll, e.g. after the bot was given the missing permissions.
+#[post("/{guild_id}/execute_change/{poll_id}")]
+pub async fn execute_poll_change(req: HttpRequest, database: web::Data<Database>) -> HttpResponse {
+    match Validator::new()
+        .require(AccessLevel::Manager)
+        .validate(&req)
+        .await
+    {
+        Ok(validation) => match parse_poll_id(&req) {
+            Some(poll_id) => {
+                let poll = match PollStore::get(&database, validation.guild_id, poll_id).await {
+                    Ok(Some(poll)) => poll,
+                    Ok(None) => {
+                        return ApiError::NotFound("Poll not found.".into()).error_response()
+                    }
+                    Err(err) => return ApiError::Internal(err).error_response(),
+                };
+                if poll.status != PollStatus::Passed {
+                    return ApiError::Conflict("Only passed polls apply their change.".into())
+                        .error_response();
+                }
+
+                let from = [ChangeStatus::Pending, ChangeStatus::Failed];
+                match execute_change(&database, validation.guild_id, &poll, &from).await {
+                    Ok(Some(outcome)) => HttpResponse::Ok().json(outcome),
+                    Ok(None) => ApiError::Conflict(
+                        "This poll has no change, or it is running or already applied.".into(),
+                    )
+                    .error_response(),
+                    Err(err) => ApiError::Internal(err).error_response(),
+                }
+            }
+            None => ApiError::BadRequest("Invalid poll id.".into()).error_response(),
         },
-        ChangeInfo {
-            identifier: ChangeIdentifier {
-                value: "assign_role".into(),
-                name: "Assign role".into(),
-            },
-            key_kind: ChangeKeyKind::Role,
-            value_kind: ChangeValueKind::Member,
+        Err(err) => err.error_response(),
+    }
+}
+
+#[post("/{guild_id}/save_settings")]
+pub async fn save_poll_settings(
+    req: HttpRequest,
+    database: web::Data<Database>,
+    settings: web::Json<PollSettings>,
+) -> HttpResponse {
+    match Validator::new()
+        .require(AccessLevel::Manager)
+        .validate(&req)
+        .await
+    {
+        Ok(validation) => {
+            let settings = settings.into_inner().without_duplicates();
+            match validate_poll_settings(validation.guild_id, &settings).await {
+                Ok(errors) if errors.is_empty() => {
+                    match PollSettingsStore::save(&database, validation.guild_id, settings).await {
+                        Ok(settings) => HttpResponse::Ok().json(settings),
+                        Err(err) => ApiError::Internal(err).error_response(),
+                    }
+                }
+                Ok(errors) => ApiError::Validation(errors).error_response(),
+                Err(err) => err.error_response(),
+            }
+        }
+        Err(err) => err.error_response(),
+    }
+}
+
+#[get("/{guild_id}/get_settings")]
+pub async fn get_poll_settings(req: HttpRequest, database: web::Data<Database>) -> HttpResponse {
+    match Validator::new().validate(&req).await {
+        Ok(validation) => match PollSettingsStore::get(&database, validation.guild_id).await {
+            Ok(settings) => HttpResponse::Ok().json(settings),
+            Err(err) => ApiError::Internal(err).error_response(),
         },
-    ])
+        Err(err) => err.error_response(),
+    }
+}
+
+#[get("/get_available_changes")]
+pub async fn get_available_poll_changes(_req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().json(get_available_changes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(page: i64) -> PollListQuery {
+        PollListQuery {
+            page: Some(page),
+            per_page: Some(PollListQuery::MAX_PER_PAGE),
+            status: None,
+        }
+    }
+
+    #[test]
+    fn rejects_pages_past_the_largest_offset() {
+        assert!(query(i64::MAX).into_filter().is_err());
+        let last = i64::MAX / PollListQuery::MAX_PER_PAGE + 1;
+        assert_eq!(query(last).into_filter().unwrap().page, last);
+        assert_eq!(query(-5).into_filter().unwrap().page, 1);
+    }
 }
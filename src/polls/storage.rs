@@ -0,0 +1,784 @@
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::helpers::database::base::Database;
+use crate::helpers::database::base::DatabaseResult;
+use crate::helpers::database::base::Executor;
+use crate::helpers::database::base::Row;
+use crate::helpers::database::base::Value;
+
+use super::changes::ChangeOutcome;
+use super::changes::ChangeStatus;
+use super::changes::PollChange;
+use super::models::Poll;
+use super::models::PollOption;
+use super::models::PollPage;
+use super::models::PollSettings;
+use super::models::PollStatus;
+
+const POLL_COLUMNS: &str = "id, question, channel_id, result_channel_id, pin, mention_role, \
+    delete_after_results, custom, role_id_needed, vote_percentage_needed_to_pass, \
+    max_votes_per_user, status, change_name, change_key, change_value, change_status, \
+    change_error";
+
+/// A change still running this many seconds after it was claimed is assumed to have died with
+/// its process, and can be claimed again.
+const CLAIM_TIMEOUT: i64 = 10 * 60;
+
+/// Matches running changes whose claim timed out, takes the running status and the cutoff.
+const STALE_CLAIM: &str =
+    "(change_status = ? AND (change_claimed_at IS NULL OR change_claimed_at < ?))";
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn stale_claim_params() -> [Value; 2] {
+    [
+        ChangeStatus::Running.to_string().into(),
+        (now() - CLAIM_TIMEOUT).into(),
+    ]
+}
+
+fn parse_change_status(value: &str) -> DatabaseResult<ChangeStatus> {
+    value.parse::<ChangeStatus>().map_err(|e| format!("{}", e))
+}
+
+fn poll_from_row(row: &Row) -> DatabaseResult<Poll> {
+    Ok(Poll {
+        id: row.get_i32(0)?,
+        question: row.get_string(1)?,
+        channel_id: row.get_u64(2)?,
+        result_channel_id: row.get_opt_u64(3)?,
+        pin: row.get_bool(4)?,
+        mention_role: row.get_bool(5)?,
+        delete_after_results: row.get_bool(6)?,
+        custom: row.get_bool(7)?,
+        role_id_needed: row.get_opt_u64(8)?,
+        vote_percentage_needed_to_pass: row.get_i16(9)?,
+        max_votes_per_user: row.get_i16(10)?,
+        status: row
+            .get_string(11)?
+            .parse::<PollStatus>()
+            .map_err(|e| format!("{}", e))?,
+        options: Vec::new(),
+        change: match row.get_opt_string(12)? {
+            Some(name) => Some(PollChange {
+                name,
+                key: row.get_string(13)?,
+                value: row.get_opt_string(14)?,
+            }),
+            None => None,
+        },
+        change_outcome: match row.get_opt_string(15)? {
+            Some(status) => Some(ChangeOutcome {
+                status: parse_change_status(&status)?,
+                error: row.get_opt_string(16)?,
+            }),
+            None => None,
+        },
+    })
+}
+
+/// The change columns of a poll, a new or edited change always starts out pending.
+fn change_params(poll: &Poll) -> Vec<Value> {
+    match &poll.change {
+        Some(change) => vec![
+            change.name.as_str().into(),
+            change.key.as_str().into(),
+            change.value.clone().into(),
+            ChangeStatus::Pending.to_string().into(),
+        ],
+        None => vec![Value::Null, Value::Null, Value::Null, Value::Null],
+    }
+}
+
+fn insert_options(connection: &mut dyn Executor, poll: &Poll) -> DatabaseResult<()> {
+    for (position, option) in poll.options.iter().enumerate() {
+        connection.execute(
+            "INSERT INTO poll_options (poll_id, position, positive, value) VALUES (?, ?, ?, ?)",
+            &[
+                poll.id.into(),
+                (position as i64).into(),
+                option.positive.into(),
+                option.value.as_str().into(),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn get_options(connection: &mut dyn Executor, poll_id: i32) -> DatabaseResult<Vec<PollOption>> {
+    connection
+        .query(
+            "SELECT positive, value FROM poll_options WHERE poll_id = ? ORDER BY position",
+            &[poll_id.into()],
+        )?
+        .iter()
+        .map(|row| {
+            Ok(PollOption {
+                positive: row.get_bool(0)?,
+                value: row.get_string(1)?,
+            })
+        })
+        .collect()
+}
+
+fn get_poll(connection: &mut dyn Executor, guild_id: u64, id: i32) -> DatabaseResult<Option<Poll>> {
+    let rows = connection.query(
+        &format!(
+            "SELECT {} FROM polls WHERE guild_id = ? AND id = ?",
+            POLL_COLUMNS
+        ),
+        &[guild_id.into(), id.into()],
+    )?;
+
+    match rows.first() {
+        Some(row) => {
+            let mut poll = poll_from_row(row)?;
+            poll.options = get_options(connection, poll.id)?;
+            Ok(Some(poll))
+        }
+        None => Ok(None),
+    }
+}
+
+pub struct PollFilter {
+    pub statuses: Vec<PollStatus>,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+fn list_polls(
+    connection: &mut dyn Executor,
+    guild_id: u64,
+    filter: &PollFilter,
+) -> DatabaseResult<PollPage> {
+    let mut condition = String::from("guild_id = ?");
+    let mut params: Vec<Value> = vec![guild_id.into()];
+    if !filter.statuses.is_empty() {
+        condition.push_str(&format!(
+            " AND status IN ({})",
+            vec!["?"; filter.statuses.len()].join(", ")
+        ));
+        params.extend(filter.statuses.iter().map(|s| s.to_string().into()));
+    }
+
+    let total = connection
+        .query(
+            &format!("SELECT COUNT(*) FROM polls WHERE {}", condition),
+            &params,
+        )?
+        .first()
+        .map(|row| row.get_i64(0))
+        .transpose()?
+        .unwrap_or(0);
+
+    params.push(filter.per_page.into());
+    params.push(((filter.page - 1) * filter.per_page).into());
+    let rows = connection.query(
+        &format!(
+            "SELECT {} FROM polls WHERE {} ORDER BY id DESC LIMIT ? OFFSET ?",
+            POLL_COLUMNS, condition
+        ),
+        &params,
+    )?;
+
+    let mut polls = Vec::with_capacity(rows.len());
+    for row in rows.iter() {
+        let mut poll = poll_from_row(row)?;
+        poll.options = get_options(connection, poll.id)?;
+        polls.push(poll);
+    }
+
+    Ok(PollPage {
+        polls,
+        total,
+        page: filter.page,
+        per_page: filter.per_page,
+    })
+}
+
+pub enum PollUpdate {
+    Updated(Box<Poll>),
+    NotFound,
+    NotDraft,
+}
+
+pub struct PollStore;
+impl PollStore {
+    /// Stores a new poll together with its options, the id sent by the client is ignored.
+    pub async fn insert(
+        database: &Database,
+        guild_id: u64,
+        mut poll: Poll,
+    ) -> DatabaseResult<Poll> {
+        database
+            .run_transaction(move |connection| {
+                let mut params: Vec<Value> = vec![
+                    guild_id.into(),
+                    poll.question.as_str().into(),
+                    poll.channel_id.into(),
+                    poll.result_channel_id.into(),
+                    poll.pin.into(),
+                    poll.mention_role.into(),
+                    poll.delete_after_results.into(),
+                    poll.custom.into(),
+                    poll.role_id_needed.into(),
+                    poll.vote_percentage_needed_to_pass.into(),
+                    poll.max_votes_per_user.into(),
+                ];
+                params.extend(change_params(&poll));
+                let id = connection.insert(
+                    "INSERT INTO polls (guild_id, question, channel_id, result_channel_id, pin, \
+                        mention_role, delete_after_results, custom, role_id_needed, \
+                        vote_percentage_needed_to_pass, max_votes_per_user, change_name, \
+                        change_key, change_value, change_status) \
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                    &params,
+                )?;
+                poll.id = id as i32;
+                insert_options(connection, &poll)?;
+                get_poll(connection, guild_id, poll.id)?
+                    .ok_or_else(|| String::from("Stored poll could not be read back."))
+            })
+            .await
+    }
+
+    pub async fn get(database: &Database, guild_id: u64, id: i32) -> DatabaseResult<Option<Poll>> {
+        database
+            .run(move |connection| get_poll(connection, guild_id, id))
+            .await
+    }
+
+    pub async fn list(
+        database: &Database,
+        guild_id: u64,
+        filter: PollFilter,
+    ) -> DatabaseResult<PollPage> {
+        database
+            .run(move |connection| list_polls(connection, guild_id, &filter))
+            .await
+    }
+
+    /// Replaces a poll and its options, only polls that are still a draft can be changed.
+    pub async fn update(
+        database: &Database,
+        guild_id: u64,
+        id: i32,
+        mut poll: Poll,
+    ) -> DatabaseResult<PollUpdate> {
+        database
+            .run_transaction(move |connection| {
+                match get_poll(connection, guild_id, id)? {
+                    None => return Ok(PollUpdate::NotFound),
+                    Some(existing) if existing.status != PollStatus::Draft => {
+                        return Ok(PollUpdate::NotDraft)
+                    }
+                    Some(_) => {}
+                }
+
+                let mut params: Vec<Value> = vec![
+                    poll.question.as_str().into(),
+                    poll.channel_id.into(),
+                    poll.result_channel_id.into(),
+                    poll.pin.into(),
+                    poll.mention_role.into(),
+                    poll.delete_after_results.into(),
+                    poll.custom.into(),
+                    poll.role_id_needed.into(),
+                    poll.vote_percentage_needed_to_pass.into(),
+                    poll.max_votes_per_user.into(),
+                ];
+                params.extend(change_params(&poll));
+                params.extend(vec![
+                    guild_id.into(),
+                    id.into(),
+                    PollStatus::Draft.to_string().into(),
+                ]);
+                connection.execute(
+                    "UPDATE polls SET question = ?, channel_id = ?, result_channel_id = ?, \
+                        pin = ?, mention_role = ?, delete_after_results = ?, custom = ?, \
+                        role_id_needed = ?, vote_percentage_needed_to_pass = ?, \
+                        max_votes_per_user = ?, change_name = ?, change_key = ?, \
+                        change_value = ?, change_status = ?, change_error = NULL \
+                        WHERE guild_id = ? AND id = ? AND status = ?",
+                    &params,
+                )?;
+                connection.execute("DELETE FROM poll_options WHERE poll_id = ?", &[id.into()])?;
+                poll.id = id;
+                insert_options(connection, &poll)?;
+
+                match get_poll(connection, guild_id, id)? {
+                    Some(poll) => Ok(PollUpdate::Updated(Box::new(poll))),
+                    None => Ok(PollUpdate::NotFound),
+                }
+            })
+            .await
+    }
+
+    /// Returns whether a poll was deleted.
+    pub async fn delete(database: &Database, guild_id: u64, id: i32) -> DatabaseResult<bool> {
+        database
+            .run_transaction(move |connection| {
+                connection.execute(
+                    "DELETE FROM poll_options WHERE poll_id IN \
+                        (SELECT id FROM polls WHERE guild_id = ? AND id = ?)",
+                    &[guild_id.into(), id.into()],
+                )?;
+                let deleted = connection.execute(
+                    "DELETE FROM polls WHERE guild_id = ? AND id = ?",
+                    &[guild_id.into(), id.into()],
+                )?;
+                Ok(deleted > 0)
+            })
+            .await
+    }
+
+    /// Passed polls whose change hasn't been picked up by the executor yet, or whose run
+    /// never finished.
+    pub async fn get_pending_changes(database: &Database) -> DatabaseResult<Vec<(u64, Poll)>> {
+        let mut params: Vec<Value> = vec![
+            PollStatus::Passed.to_string().into(),
+            ChangeStatus::Pending.to_string().into(),
+        ];
+        params.extend(stale_claim_params());
+
+        database
+            .run(move |connection| {
+                let rows = connection.query(
+                    &format!(
+                        "SELECT guild_id, id FROM polls WHERE status = ? \
+                            AND (change_status = ? OR {}) ORDER BY id",
+                        STALE_CLAIM
+                    ),
+                    &params,
+                )?;
+
+                let mut polls = Vec::with_capacity(rows.len());
+                for row in rows.iter() {
+                    let guild_id = row.get_u64(0)?;
+                    if let Some(poll) = get_poll(connection, guild_id, row.get_i32(1)?)? {
+                        polls.push((guild_id, poll));
+                    }
+                }
+                Ok(polls)
+            })
+            .await
+    }
+
+    /// Marks the change of a passed poll as running, returns false when it isn't in one of
+    /// the `from` states anymore so a change is never applied twice. A running change whose
+    /// claim timed out can always be claimed again.
+    pub async fn claim_change(
+        database: &Database,
+        guild_id: u64,
+        id: i32,
+        from: &[ChangeStatus],
+    ) -> DatabaseResult<bool> {
+        let mut params: Vec<Value> = vec![
+            ChangeStatus::Running.to_string().into(),
+            now().into(),
+            guild_id.into(),
+            id.into(),
+            PollStatus::Passed.to_string().into(),
+        ];
+        params.extend(from.iter().map(|s| s.to_string().into()));
+        params.extend(stale_claim_params());
+        let placeholders = vec!["?"; from.len()].join(", ");
+
+        database
+            .run(move |connection| {
+                let affected = connection.execute(
+                    &format!(
+                        "UPDATE polls SET change_status = ?, change_claimed_at = ? \
+                            WHERE guild_id = ? AND id = ? AND status = ? \
+                            AND (change_status IN ({}) OR {})",
+                        placeholders, STALE_CLAIM
+                    ),
+                    &params,
+                )?;
+                Ok(affected > 0)
+            })
+            .await
+    }
+
+    pub async fn set_change_outcome(
+        database: &Database,
+        guild_id: u64,
+        id: i32,
+        outcome: ChangeOutcome,
+    ) -> DatabaseResult<()> {
+        database
+            .run(move |connection| {
+                connection.execute(
+                    "UPDATE polls SET change_status = ?, change_error = ?, \
+                        change_executed_at = CURRENT_TIMESTAMP WHERE guild_id = ? AND id = ?",
+                    &[
+                        outcome.status.to_string().into(),
+                        outcome.error.into(),
+                        guild_id.into(),
+                        id.into(),
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+}
+
+fn get_poll_settings(
+    connection: &mut dyn Executor,
+    guild_id: u64,
+) -> DatabaseResult<Option<PollSettings>> {
+    let exists = !connection
+        .query(
+            "SELECT guild_id FROM poll_settings WHERE guild_id = ?",
+            &[guild_id.into()],
+        )?
+        .is_empty();
+    if !exists {
+        return Ok(None);
+    }
+
+    let allowed_channels = connection
+        .query(
+            "SELECT channel_id FROM poll_settings_channels WHERE guild_id = ? ORDER BY channel_id",
+            &[guild_id.into()],
+        )?
+        .iter()
+        .map(|row| Ok(row.get_u64(0)?.to_string()))
+        .collect::<DatabaseResult<Vec<String>>>()?;
+
+    let allowed_changes = connection
+        .query(
+            "SELECT change_name FROM poll_settings_changes WHERE guild_id = ? ORDER BY change_name",
+            &[guild_id.into()],
+        )?
+        .iter()
+        .map(|row| row.get_string(0))
+        .collect::<DatabaseResult<Vec<String>>>()?;
+
+    Ok(Some(PollSettings {
+        allowed_channels,
+        allowed_changes,
+    }))
+}
+
+pub struct PollSettingsStore;
+impl PollSettingsStore {
+    /// Returns the stored settings of a guild, or the defaults when none were saved yet.
+    pub async fn get(database: &Database, guild_id: u64) -> DatabaseResult<PollSettings> {
+        database
+            .run(move |connection| Ok(get_poll_settings(connection, guild_id)?.unwrap_or_default()))
+            .await
+    }
+
+    /// Replaces the settings of a guild, expects channel ids to be validated already.
+    pub async fn save(
+        database: &Database,
+        guild_id: u64,
+        settings: PollSettings,
+    ) -> DatabaseResult<PollSettings> {
+        database
+            .run_transaction(move |connection| {
+                for table in &[
+                    "poll_settings",
+                    "poll_settings_channels",
+                    "poll_settings_changes",
+                ] {
+                    connection.execute(
+                        &format!("DELETE FROM {} WHERE guild_id = ?", table),
+                        &[guild_id.into()],
+                    )?;
+                }
+
+                connection.execute(
+                    "INSERT INTO poll_settings (guild_id) VALUES (?)",
+                    &[guild_id.into()],
+                )?;
+                for channel_id in settings.allowed_channels.iter() {
+                    let channel_id = channel_id
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid channel id `{}`.", channel_id))?;
+                    connection.execute(
+                        "INSERT INTO poll_settings_channels (guild_id, channel_id) VALUES (?, ?)",
+                        &[guild_id.into(), channel_id.into()],
+                    )?;
+                }
+                for change in settings.allowed_changes.iter() {
+                    connection.execute(
+                        "INSERT INTO poll_settings_changes (guild_id, change_name) VALUES (?, ?)",
+                        &[guild_id.into(), change.as_str().into()],
+                    )?;
+                }
+
+                get_poll_settings(connection, guild_id)?
+                    .ok_or_else(|| String::from("Stored poll settings could not be read back."))
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use crate::helpers::database::base::Backend;
+    use crate::helpers::database::base::DatabaseSettings;
+    use crate::helpers::database::migrations::run_migrations;
+
+    use super::*;
+
+    const GUILD_ID: u64 = 1;
+
+    fn block_on<F: Future + 'static>(future: F) -> F::Output {
+        actix_web::rt::System::new("test").block_on(future)
+    }
+
+    fn database() -> Database {
+        let settings = DatabaseSettings {
+            backend: Backend::Sqlite,
+            name: ":memory:".into(),
+            ..Default::default()
+        };
+        let database = Database::connect(&settings).unwrap();
+        run_migrations(&database).unwrap();
+        database
+    }
+
+    fn poll(question: &str, options: &[&str]) -> Poll {
+        Poll {
+            id: 0,
+            status: PollStatus::Draft,
+            question: question.into(),
+            channel_id: 10,
+            result_channel_id: None,
+            pin: false,
+            mention_role: false,
+            delete_after_results: false,
+            custom: false,
+            role_id_needed: None,
+            vote_percentage_needed_to_pass: 50,
+            max_votes_per_user: 1,
+            options: options
+                .iter()
+                .map(|value| PollOption {
+                    positive: true,
+                    value: value.to_string(),
+                })
+                .collect(),
+            change: Some(PollChange {
+                name: "create_channel".into(),
+                key: "general".into(),
+                value: None,
+            }),
+            change_outcome: None,
+        }
+    }
+
+    fn set_status(database: &Database, id: i32, status: PollStatus) {
+        database
+            .with_connection(|connection| {
+                connection.execute(
+                    "UPDATE polls SET status = ? WHERE id = ?",
+                    &[status.to_string().into(), id.into()],
+                )
+            })
+            .unwrap();
+    }
+
+    fn values(poll: &Poll) -> Vec<&str> {
+        poll.options.iter().map(|o| o.value.as_str()).collect()
+    }
+
+    #[test]
+    fn insert_returns_the_stored_poll() {
+        let database = database();
+        let (first, second) = block_on(async move {
+            let mut sent = poll("First?", &["c", "a", "b"]);
+            sent.id = 99;
+            let first = PollStore::insert(&database, GUILD_ID, sent).await.unwrap();
+            let second = PollStore::insert(&database, GUILD_ID, poll("Second?", &[]))
+                .await
+                .unwrap();
+            (first, second)
+        });
+
+        assert_ne!(first.id, 99);
+        assert_eq!(second.id, first.id + 1);
+        assert_eq!(values(&first), ["c", "a", "b"]);
+        assert_eq!(first.change_outcome.unwrap().status, ChangeStatus::Pending);
+    }
+
+    #[test]
+    fn list_filters_by_status_and_pages() {
+        let database = database();
+        let (active, first_page, second_page) = block_on(async move {
+            let mut ids = Vec::new();
+            for question in ["1", "2", "3"].iter() {
+                let poll = PollStore::insert(&database, GUILD_ID, poll(question, &["a"]))
+                    .await
+                    .unwrap();
+                ids.push(poll.id);
+            }
+            set_status(&database, ids[1], PollStatus::Active);
+
+            let list = |statuses: Vec<PollStatus>, page: i64| {
+                let database = database.clone();
+                async move {
+                    let filter = PollFilter {
+                        statuses,
+                        page,
+                        per_page: 2,
+                    };
+                    PollStore::list(&database, GUILD_ID, filter).await.unwrap()
+                }
+            };
+            (
+                list(vec![PollStatus::Active], 1).await,
+                list(Vec::new(), 1).await,
+                list(Vec::new(), 2).await,
+            )
+        });
+
+        let questions = |page: &PollPage| -> Vec<String> {
+            page.polls.iter().map(|p| p.question.clone()).collect()
+        };
+        assert_eq!(active.total, 1);
+        assert_eq!(questions(&active), ["2"]);
+        assert_eq!(first_page.total, 3);
+        assert_eq!(questions(&first_page), ["3", "2"]);
+        assert_eq!(questions(&second_page), ["1"]);
+        assert_eq!(values(&second_page.polls[0]), ["a"]);
+    }
+
+    #[test]
+    fn update_replaces_drafts_only() {
+        let database = database();
+        let (updated, not_draft, not_found) = block_on(async move {
+            let draft = PollStore::insert(&database, GUILD_ID, poll("Draft?", &["a", "b"]))
+                .await
+                .unwrap();
+            let active = PollStore::insert(&database, GUILD_ID, poll("Active?", &["a"]))
+                .await
+                .unwrap();
+            set_status(&database, active.id, PollStatus::Active);
+
+            let updated = PollStore::update(&database, GUILD_ID, draft.id, poll("New?", &["z"]))
+                .await
+                .unwrap();
+            let not_draft = PollStore::update(&database, GUILD_ID, active.id, poll("New?", &[]))
+                .await
+                .unwrap();
+            let not_found = PollStore::update(&database, 2, draft.id, poll("New?", &[]))
+                .await
+                .unwrap();
+            (updated, not_draft, not_found)
+        });
+
+        match updated {
+            PollUpdate::Updated(poll) => {
+                assert_eq!(poll.question, "New?");
+                assert_eq!(values(&poll), ["z"]);
+            }
+            _ => panic!("the draft was not updated"),
+        }
+        assert!(matches!(not_draft, PollUpdate::NotDraft));
+        assert!(matches!(not_found, PollUpdate::NotFound));
+    }
+
+    #[test]
+    fn delete_removes_the_options() {
+        let database = database();
+        let options_left = database.clone();
+        let (deleted, deleted_again, found) = block_on(async move {
+            let poll = PollStore::insert(&database, GUILD_ID, poll("Gone?", &["a", "b"]))
+                .await
+                .unwrap();
+            let deleted = PollStore::delete(&database, GUILD_ID, poll.id)
+                .await
+                .unwrap();
+            let deleted_again = PollStore::delete(&database, GUILD_ID, poll.id)
+                .await
+                .unwrap();
+            let found = PollStore::get(&database, GUILD_ID, poll.id).await.unwrap();
+            (deleted, deleted_again, found)
+        });
+
+        assert!(deleted);
+        assert!(!deleted_again);
+        assert!(found.is_none());
+        let rows = options_left
+            .with_connection(|connection| connection.query("SELECT id FROM poll_options", &[]))
+            .unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn claim_change_claims_once_until_the_claim_is_stale() {
+        let database = database();
+        let (first, second, stale, after_stale) = block_on(async move {
+            let poll = PollStore::insert(&database, GUILD_ID, poll("Passed?", &[]))
+                .await
+                .unwrap();
+            set_status(&database, poll.id, PollStatus::Passed);
+            let claim = || {
+                let database = database.clone();
+                async move {
+                    PollStore::claim_change(&database, GUILD_ID, poll.id, &[ChangeStatus::Pending])
+                        .await
+                        .unwrap()
+                }
+            };
+
+            let first = claim().await;
+            let second = claim().await;
+            database
+                .with_connection(|connection| {
+                    connection.execute(
+                        "UPDATE polls SET change_claimed_at = ? WHERE id = ?",
+                        &[(now() - CLAIM_TIMEOUT - 1).into(), poll.id.into()],
+                    )
+                })
+                .unwrap();
+            let stale = claim().await;
+            let after_stale = claim().await;
+            (first, second, stale, after_stale)
+        });
+
+        assert!(first);
+        assert!(!second);
+        assert!(stale);
+        assert!(!after_stale);
+    }
+
+    #[test]
+    fn poll_settings_default_and_round_trip() {
+        let database = database();
+        let (defaults, saved, loaded) = block_on(async move {
+            let defaults = PollSettingsStore::get(&database, GUILD_ID).await.unwrap();
+            let settings = PollSettings {
+                allowed_channels: vec!["20".into(), "10".into()],
+                allowed_changes: vec!["create_channel".into()],
+            };
+            let saved = PollSettingsStore::save(&database, GUILD_ID, settings)
+                .await
+                .unwrap();
+            let loaded = PollSettingsStore::get(&database, GUILD_ID).await.unwrap();
+            (defaults, saved, loaded)
+        });
+
+        assert!(defaults.allowed_channels.is_empty());
+        assert_eq!(
+            defaults.allowed_changes,
+            PollSettings::default().allowed_changes
+        );
+        assert_eq!(saved.allowed_channels, ["10", "20"]);
+        assert_eq!(loaded.allowed_channels, ["10", "20"]);
+        assert_eq!(loaded.allowed_changes, ["create_channel"]);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::discord::base_api::Callable;
+use crate::discord::calls::ChannelKind;
+use crate::discord::calls::GetGuild;
+use crate::discord::calls::GetMember;
+use crate::discord::discord_base::get_bot_user_id;
+use crate::discord::discord_base::DiscordCall;
+use crate::discord::models::Channel;
+use crate::discord::permissions::PermissionCalculator;
+use crate::helpers::errors::ApiResult;
+use crate::helpers::errors::ValidationErrors;
+use crate::helpers::repositories::discord::ChannelRepository;
+use crate::helpers::repositories::discord::ChannelRepositoryOptions;
+use crate::helpers::repositories::discord::MemberRepository;
+use crate::helpers::repositories::discord::Repository;
+use crate::helpers::repositories::discord::RoleRepository;
+use crate::helpers::repositories::discord::SharedRepositoryOptions;
+
+use super::changes::get_available_changes;
+use super::changes::get_change_info;
+use super::changes::ChangeInfo;
+use super::changes::ChangeKeyKind;
+use super::changes::ChangeValueKind;
+use super::changes::PollChange;
+use super::models::Poll;
+use super::models::PollSettings;
+
+/// Matches the `poll_options.value` column.
+const MAX_OPTION_LENGTH: usize = 255;
+
+async fn get_text_channels(guild_id: u64) -> ApiResult<Vec<Channel>> {
+    ChannelRepository::get(&ChannelRepositoryOptions(guild_id, ChannelKind::GuildText)).await
+}
+
+/// Checks the channels against the guild's text channels and the changes against the catalogue.
+/// The outer error is reserved for failures while fetching the guild state.
+pub async fn validate_poll_settings(
+    guild_id: u64,
+    settings: &PollSettings,
+) -> ApiResult<ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    let channels = get_text_channels(guild_id).await?;
+    for (index, id) in settings.allowed_channels.iter().enumerate() {
+        if !channels.iter().any(|c| &c.id == id) {
+            errors.add(
+                &format!("allowed_channels[{}]", index),
+                "Unknown text channel.",
+            );
+        }
+    }
+
+    let available_changes = get_available_changes();
+    for (index, name) in settings.allowed_changes.iter().enumerate() {
+        if !available_changes
+            .iter()
+            .any(|c| &c.identifier.value == name)
+        {
+            errors.add(&format!("allowed_changes[{}]", index), "Unknown change.");
+        }
+    }
+
+    Ok(errors)
+}
+
+async fn role_exists(guild_id: u64, id: &str) -> ApiResult<bool> {
+    let roles = RoleRepository::get(&SharedRepositoryOptions(guild_id)).await?;
+    Ok(roles.iter().any(|r| r.id == id))
+}
+
+async fn member_exists(guild_id: u64, id: &str) -> ApiResult<bool> {
+    let members = MemberRepository::get(&SharedRepositoryOptions(guild_id)).await?;
+    Ok(members.iter().any(|m| m.id == id))
+}
+
+/// Who the bot is in a guild, needed to compute its permissions.
+struct BotMember {
+    id: String,
+    roles: Vec<String>,
+    is_owner: bool,
+}
+
+async fn get_bot_member(guild_id: u64) -> ApiResult<BotMember> {
+    let user_id = get_bot_user_id().await?;
+    let call = DiscordCall::bot();
+    let member = call.call(GetMember { guild_id, user_id }).await?;
+    let guild = call.call(GetGuild { guild_id }).await?;
+
+    let id = user_id.to_string();
+    Ok(BotMember {
+        is_owner: guild.owner_id == id,
+        id,
+        roles: member.roles,
+    })
+}
+
+/// Adds errors when the bot wouldn't be allowed to apply the change, so a poll can't pass only
+/// for its change to fail.
+async fn check_bot_permissions(
+    guild_id: u64,
+    change: &PollChange,
+    info: &ChangeInfo,
+    channels: &[Channel],
+    errors: &mut ValidationErrors,
+) -> ApiResult<()> {
+    let bot = get_bot_member(guild_id).await?;
+    let roles = RoleRepository::get(&SharedRepositoryOptions(guild_id)).await?;
+    let guild_id = guild_id.to_string();
+    let calculator = PermissionCalculator {
+        guild_id: &guild_id,
+        roles: &roles,
+        member_id: &bot.id,
+        member_roles: &bot.roles,
+        is_owner: bot.is_owner,
+    };
+
+    let permissions = match info.key_kind {
+        ChangeKeyKind::Channel => match channels.iter().find(|c| c.id == change.key) {
+            Some(channel) => calculator.channel_permissions(&channel.permission_overwrites),
+            None => return Ok(()),
+        },
+        _ => calculator.guild_permissions(),
+    };
+    let missing = info.required_permissions - permissions;
+    if !missing.is_empty() {
+        errors.add(
+            "change.name",
+            &format!("The bot is missing the {:?} permission.", missing),
+        );
+    }
+
+    if let ChangeKeyKind::Role = info.key_kind {
+        if let Some(role) = roles.iter().find(|r| r.id == change.key) {
+            if !bot.is_owner && role.position >= calculator.highest_role_position() {
+                errors.add(
+                    "change.key",
+                    "The bot can only assign roles below its highest role.",
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn validate_change(
+    guild_id: u64,
+    change: &PollChange,
+    settings: &PollSettings,
+    channels: &[Channel],
+    errors: &mut ValidationErrors,
+) -> ApiResult<()> {
+    let info = match get_change_info(&change.name) {
+        Some(info) => info,
+        None => {
+            errors.add("change.name", "Unknown change.");
+            return Ok(());
+        }
+    };
+    if !settings.allowed_changes.contains(&change.name) {
+        errors.add("change.name", "This change is not allowed in this guild.");
+    }
+
+    let key_error = match info.key_kind {
+        ChangeKeyKind::String if change.key.trim().is_empty() => Some("A value is required."),
+        ChangeKeyKind::String if change.key.len() > 100 => {
+            Some("Can't be longer than 100 characters.")
+        }
+        ChangeKeyKind::String => None,
+        ChangeKeyKind::Channel if !channels.iter().any(|c| c.id == change.key) => {
+            Some("Unknown text channel.")
+        }
+        ChangeKeyKind::Channel => None,
+        ChangeKeyKind::Role if !role_exists(guild_id, &change.key).await? => Some("Unknown role."),
+        ChangeKeyKind::Role => None,
+        ChangeKeyKind::Member if !member_exists(guild_id, &change.key).await? => {
+            Some("Unknown member.")
+        }
+        ChangeKeyKind::Member => None,
+    };
+    if let Some(message) = key_error {
+        errors.add("change.key", message);
+    }
+
+    let value_error = match (&info.value_kind, &change.value) {
+        (ChangeValueKind::None, None) => None,
+        (ChangeValueKind::None, Some(_)) => Some("This change doesn't take a value."),
+        (_, None) => Some("A value is required."),
+        (ChangeValueKind::String, Some(value)) if value.trim().is_empty() => {
+            Some("A value is required.")
+        }
+        (ChangeValueKind::String, Some(_)) => None,
+        (ChangeValueKind::Role, Some(value)) if !role_exists(guild_id, value).await? => {
+            Some("Unknown role.")
+        }
+        (ChangeValueKind::Role, Some(_)) => None,
+        (ChangeValueKind::Member, Some(value)) if !member_exists(guild_id, value).await? => {
+            Some("Unknown member.")
+        }
+        (ChangeValueKind::Member, Some(_)) => None,
+    };
+    if let Some(message) = value_error {
+        errors.add("change.value", message);
+    }
+
+    check_bot_permissions(guild_id, change, &info, channels, errors).await
+}
+
+/// Checks a submitted poll against the guild's channels, roles and poll settings.
+/// The outer error is reserved for failures while fetching the guild state.
+pub async fn validate_poll(
+    guild_id: u64,
+    poll: &Poll,
+    settings: &PollSettings,
+) -> ApiResult<ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    if poll.question.trim().is_empty() {
+        errors.add("question", "A question is required.");
+    }
+
+    let channels = get_text_channels(guild_id).await?;
+    let channel_id = poll.channel_id.to_string();
+    if !channels.iter().any(|c| c.id == channel_id) {
+        errors.add("channel_id", "Unknown text channel.");
+    } else if !settings.allowed_channels.is_empty()
+        && !settings.allowed_channels.contains(&channel_id)
+    {
+        errors.add("channel_id", "Polls are not allowed in this channel.");
+    }
+
+    if let Some(result_channel_id) = poll.result_channel_id {
+        let result_channel_id = result_channel_id.to_string();
+        if !channels.iter().any(|c| c.id == result_channel_id) {
+            errors.add("result_channel_id", "Unknown text channel.");
+        }
+    }
+
+    if let Some(role_id_needed) = poll.role_id_needed {
+        if !role_exists(guild_id, &role_id_needed.to_string()).await? {
+            errors.add("role_id_needed", "Unknown role.");
+        }
+    }
+
+    check_poll_fields(poll, &mut errors);
+
+    if let Some(change) = &poll.change {
+        validate_change(guild_id, change, settings, &channels, &mut errors).await?;
+    }
+
+    Ok(errors)
+}
+
+/// The checks that only need the poll itself.
+fn check_poll_fields(poll: &Poll, errors: &mut ValidationErrors) {
+    if !(0..=100).contains(&poll.vote_percentage_needed_to_pass) {
+        errors.add(
+            "vote_percentage_needed_to_pass",
+            "Must be between 0 and 100.",
+        );
+    }
+
+    if poll.max_votes_per_user < 1 {
+        errors.add("max_votes_per_user", "Must be at least 1.");
+    } else if !poll.options.is_empty() && poll.max_votes_per_user as usize > poll.options.len() {
+        errors.add(
+            "max_votes_per_user",
+            "Can't be higher than the amount of options.",
+        );
+    }
+
+    if poll.options.is_empty() {
+        errors.add("options", "At least one option is required.");
+    }
+    for (index, option) in poll.options.iter().enumerate() {
+        let field = format!("options[{}].value", index);
+        if option.value.trim().is_empty() {
+            errors.add(&field, "A value is required.");
+        } else if option.value.chars().count() > MAX_OPTION_LENGTH {
+            errors.add(&field, "Can't be longer than 255 characters.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polls::models::PollOption;
+    use crate::polls::models::PollStatus;
+
+    fn poll(options: &[&str]) -> Poll {
+        Poll {
+            id: 0,
+            status: PollStatus::Draft,
+            question: "Question?".into(),
+            channel_id: 1,
+            result_channel_id: None,
+            pin: false,
+            mention_role: false,
+            delete_after_results: false,
+            custom: false,
+            role_id_needed: None,
+            vote_percentage_needed_to_pass: 50,
+            max_votes_per_user: 1,
+            options: options
+                .iter()
+                .map(|value| PollOption {
+                    positive: true,
+                    value: value.to_string(),
+                })
+                .collect(),
+            change: None,
+            change_outcome: None,
+        }
+    }
+
+    fn fields(poll: &Poll) -> Vec<String> {
+        let mut errors = ValidationErrors::default();
+        check_poll_fields(poll, &mut errors);
+        errors.errors.into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn accepts_a_valid_poll() {
+        assert!(fields(&poll(&["yes", "no"])).is_empty());
+    }
+
+    #[test]
+    fn percentage_must_be_a_percentage() {
+        for percentage in [-1, 101].iter() {
+            let mut poll = poll(&["yes"]);
+            poll.vote_percentage_needed_to_pass = *percentage;
+            assert_eq!(fields(&poll), ["vote_percentage_needed_to_pass"]);
+        }
+    }
+
+    #[test]
+    fn max_votes_must_fit_the_options() {
+        let mut poll = poll(&["yes", "no"]);
+        poll.max_votes_per_user = 0;
+        assert_eq!(fields(&poll), ["max_votes_per_user"]);
+        poll.max_votes_per_user = 3;
+        assert_eq!(fields(&poll), ["max_votes_per_user"]);
+        poll.max_votes_per_user = 2;
+        assert!(fields(&poll).is_empty());
+    }
+
+    #[test]
+    fn options_must_have_values() {
+        assert_eq!(fields(&poll(&[])), ["options"]);
+        assert_eq!(fields(&poll(&["yes", " "])), ["options[1].value"]);
+    }
+
+    #[test]
+    fn option_values_fit_the_column() {
+        let longest = "é".repeat(MAX_OPTION_LENGTH);
+        let too_long = "a".repeat(MAX_OPTION_LENGTH + 1);
+        assert!(fields(&poll(&[&longest])).is_empty());
+        assert_eq!(fields(&poll(&["yes", &too_long])), ["options[1].value"]);
+    }
+}